@@ -5,7 +5,12 @@
 use cfg_if::cfg_if;
 use core::fmt::Write;
 use dioxide as _; // global logger + panicking-behavior + memory layout
+use dioxide::bmp280;
+use dioxide::compat::{Eh0Delay, Eh0I2c};
+#[cfg(feature = "data-ready-pin")]
+use dioxide::data_ready::DataReadyPin;
 use dioxide::scd30;
+use dioxide::ui::OptionalValue;
 use embedded_graphics::{
     geometry::{Point, Size},
     mono_font::MonoTextStyle,
@@ -53,7 +58,8 @@ fn clear_measurement<D: DrawTarget<Color = BinaryColor>>(target: &mut D) -> Resu
     Ok(())
 }
 
-fn draw_measurement<D: DrawTarget<Color = BinaryColor>>(target: &mut D, measurement: &scd30::Measurement) -> Result<(), D::Error> {
+fn draw_measurement<D: DrawTarget<Color = BinaryColor>>(target: &mut D, measurement: &scd30::ValidMeasurement) -> Result<(), D::Error> {
+    let measurement = measurement.measurement();
     let style = MonoTextStyle::new(&PROFONT_18_POINT, BinaryColor::On);
     let mut message: String<16> = String::new();
 
@@ -65,13 +71,13 @@ fn draw_measurement<D: DrawTarget<Color = BinaryColor>>(target: &mut D, measurem
         .draw(target)?;
 
     message.clear();
-    write!(&mut message, "T:   {:.2} °C", measurement.temperature_celsius)
+    write!(&mut message, "T:   {:.2} °C", OptionalValue(measurement.temperature_celsius))
         .expect("failed to write to buffer");
     Text::new(&message, Point::new(20, 90), style)
         .draw(target)?;
 
     message.clear();
-    write!(&mut message, "RH:  {:.2} %", measurement.humidity_percent)
+    write!(&mut message, "RH:  {:.2} %", OptionalValue(measurement.humidity_percent))
         .expect("failed to write to buffer");
     Text::new(&message, Point::new(20, 110), style)
         .draw(target)?;
@@ -100,7 +106,28 @@ fn main() -> ! {
     let sda = pins_0.p0_31.into_floating_input().degrade();
     let i2c_pins = twim::Pins{ scl, sda };
     let i2c = Twim::new(board.TWIM0, i2c_pins, twim::Frequency::K100);
-    let mut sensor = scd30::Scd30::new(i2c);
+    let shared_i2c = shared_bus::BusManagerSimple::new(i2c);
+    let sensor_timer = Timer::new(board.TIMER2);
+    #[cfg(not(feature = "sensor-senseair-s8"))]
+    let sensor_bus = Eh0I2c::new(shared_i2c.acquire_i2c());
+    #[cfg(feature = "sensor-senseair-s8")]
+    let sensor_bus = dioxide::co2_sensor_uart(
+        board.UARTE0,
+        pins_0.p0_03.into_floating_input().degrade(),
+        pins_0.p0_04.into_push_pull_output(Level::High).degrade(),
+    );
+    let mut barometer = bmp280::Bmp280::new(Eh0I2c::new(shared_i2c.acquire_i2c()), bmp280::I2C_ADDRESS_PRIMARY)
+        .map_err(|error| defmt::warn!("initializing barometer failed: {}", error))
+        .ok();
+    let mut reference = dioxide::probe_reference_sensor(Eh0I2c::new(shared_i2c.acquire_i2c()), Eh0Delay::new(Timer::new(board.TIMER3)));
+    let mut voc_sensor = dioxide::probe_voc_sensor(Eh0I2c::new(shared_i2c.acquire_i2c()), Eh0Delay::new(Timer::new(board.TIMER4)));
+    let mut voc_index = dioxide::voc_index_algorithm();
+    #[cfg(feature = "data-ready-pin")]
+    let mut data_ready = {
+        let mut core = cortex_m::Peripherals::take().unwrap();
+        let rdy = pins_0.p0_29.into_pulldown_input().degrade();
+        DataReadyPin::new(board.GPIOTE, rdy, &mut core.SCB)
+    };
 
     // TODO: Why do we need to degrade two of the pins?
     let din = pins_1.p1_01.into_push_pull_output(Level::Low).degrade();
@@ -134,10 +161,9 @@ fn main() -> ! {
     let temperature = temp.measure();
     defmt::info!("temperature: {} °C", temperature.to_num::<f32>());
 
-    let sensor_fw_version = sensor.get_firmware_version().unwrap();
-    defmt::info!("SCD30 firmware version: {:?}", sensor_fw_version);
-    let pressure_mbar = 1020_u16;
-    sensor.start_continuous_measurement(pressure_mbar).unwrap();
+    let mut pressure = barometer.as_mut()
+        .and_then(dioxide::read_ambient_pressure);
+    let mut sensor = dioxide::start_co2_sensor(sensor_bus, Eh0Delay::new(sensor_timer), pressure);
 
 
     let header_style = MonoTextStyle::new(&PROFONT_24_POINT, BinaryColor::On);
@@ -150,19 +176,33 @@ fn main() -> ! {
 
     defmt::info!("Entering loop ...");
 
+    #[cfg(not(feature = "data-ready-pin"))]
+    let mut cycle = dioxide::start_measurement_cycle(timer);
+
     let mut updates = 0usize;
 
     loop {
+        // The sensor signals when the measurement of the current cycle is
+        // available.
+        #[cfg(feature = "data-ready-pin")]
+        data_ready.wait();
+
         led_1.on().unwrap();
         if button_1.is_active().unwrap() {
             defmt::info!("Button 1 pressed");
             led_2.on().unwrap();
         }
 
-        if sensor.is_measurement_ready().unwrap() {
-            let measurement = sensor.get_measurement().unwrap();
-            defmt::info!("measurement: {:?}", measurement);
+        let measurement = dioxide::measure(
+            &mut sensor,
+            barometer.as_mut(),
+            &mut pressure,
+            reference.as_mut(),
+            voc_sensor.as_mut(),
+            &mut voc_index,
+        );
 
+        if let Some(measurement) = measurement {
             epd.wake_up(&mut spi, &mut epd_timer).unwrap();
 
             defmt::info!("updates: {}", updates);
@@ -184,9 +224,9 @@ fn main() -> ! {
             updates += 1;
         }
 
-        timer.delay_ms(5000u32);
         led_1.off().unwrap();
         led_2.off().unwrap();
-        timer.delay_ms(5000u32);
+        #[cfg(not(feature = "data-ready-pin"))]
+        dioxide::wait_for_next_cycle(&mut cycle);
     }
 }
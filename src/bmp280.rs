@@ -0,0 +1,434 @@
+// A driver for the Bosch BMP280 barometer and the BME280 combined sensor. It
+// provides temperature and pressure readings, primarily for feeding the
+// ambient pressure compensation of the SCD30. Humidity from the BME280 is not
+// used.
+
+
+use core::fmt;
+use defmt::Format;
+use embedded_hal_1::i2c::I2c;
+
+
+
+
+// A custom error type for reporting errors from both, the driver itself and
+// the underlying I2C implementation.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error<E> {
+    // The device did not identify itself as BMP280 or BME280. Carries the
+    // chip ID read from it.
+    UnknownChipId(u8),
+    // The device has not completed its first conversion yet and still reports
+    // the reset values.
+    NotReady,
+    I2cError(E),
+}
+
+
+// Reports an error from initialization and hands back the I2C bus.
+pub struct InitError<E, I2C> {
+    pub error: Error<E>,
+    pub i2c: I2C,
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, Format, PartialEq)]
+pub enum Chip {
+    Bmp280,
+    Bme280,
+}
+
+
+#[derive(Clone, Copy, Debug, Format, PartialEq)]
+pub struct Measurement {
+    pub temperature_celsius: f32,
+    pub pressure_pa: f32,
+}
+
+
+// The trimming parameters programmed into each device during production.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p2: i16,
+    p3: i16,
+    p4: i16,
+    p5: i16,
+    p6: i16,
+    p7: i16,
+    p8: i16,
+    p9: i16,
+}
+
+
+pub struct Bmp280<I2C> {
+    i2c: I2C,
+    address: u8,
+    chip: Chip,
+    calibration: Calibration,
+}
+
+
+
+
+// The address with SDO connected to GND.
+pub const I2C_ADDRESS_PRIMARY: u8 = 0x76;
+// The address with SDO connected to VDDIO.
+pub const I2C_ADDRESS_SECONDARY: u8 = 0x77;
+
+const REG_CALIBRATION: u8 = 0x88;
+const REG_CHIP_ID: u8 = 0xd0;
+const REG_CTRL_MEAS: u8 = 0xf4;
+const REG_CONFIG: u8 = 0xf5;
+const REG_PRESS_MSB: u8 = 0xf7;
+
+const CALIBRATION_LEN: usize = 24;
+const DATA_LEN: usize = 6;
+
+const CHIP_ID_BMP280: u8 = 0x58;
+const CHIP_ID_BME280: u8 = 0x60;
+
+// Temperature oversampling x2, pressure oversampling x16 and normal mode.
+const CTRL_MEAS_NORMAL: u8 = (0b010 << 5) | (0b101 << 2) | 0b11;
+// A standby time of 1000 ms between conversions and IIR filter coefficient
+// 16. This matches the rate of SCD30 measurements and suppresses short
+// pressure spikes from doors or wind.
+const CONFIG_STANDBY_1000MS_FILTER_16: u8 = (0b101 << 5) | (0b100 << 2);
+
+// The value of the data registers until the first conversion completed.
+const ADC_RESET_VALUE: i32 = 0x80000;
+
+
+
+
+impl<E: fmt::Debug> Format for Error<E> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::UnknownChipId(id) => defmt::write!(f, "UnknownChipId({=u8:#x})", id),
+            Error::NotReady => defmt::write!(f, "NotReady"),
+            Error::I2cError(error) => defmt::write!(f, "I2cError({})", defmt::Debug2Format(error)),
+        }
+    }
+}
+
+
+impl<E: fmt::Debug, I2C> fmt::Debug for InitError<E, I2C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InitError")
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+
+impl<E: fmt::Debug, I2C> Format for InitError<E, I2C> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "InitError{{ error: {} }}", self.error);
+    }
+}
+
+
+impl<E> From<E> for Error<E> {
+    fn from(error: E) -> Self {
+        Error::I2cError(error)
+    }
+}
+
+
+impl Calibration {
+    fn from_registers(data: &[u8; CALIBRATION_LEN]) -> Self {
+        let unsigned = |index: usize| u16::from_le_bytes([data[index], data[index + 1]]);
+        let signed = |index: usize| i16::from_le_bytes([data[index], data[index + 1]]);
+
+        Calibration {
+            t1: unsigned(0),
+            t2: signed(2),
+            t3: signed(4),
+            p1: unsigned(6),
+            p2: signed(8),
+            p3: signed(10),
+            p4: signed(12),
+            p5: signed(14),
+            p6: signed(16),
+            p7: signed(18),
+            p8: signed(20),
+            p9: signed(22),
+        }
+    }
+
+
+    // Returns the fine resolution temperature used for compensating the
+    // pressure as well as the temperature in 0.01 °C. This is the integer
+    // implementation from the datasheet.
+    fn compensate_temperature(&self, adc_t: i32) -> (i32, i32) {
+        let t1 = i32::from(self.t1);
+        let t2 = i32::from(self.t2);
+        let t3 = i32::from(self.t3);
+
+        let var1 = (((adc_t >> 3) - (t1 << 1)) * t2) >> 11;
+        let var2 = (((((adc_t >> 4) - t1) * ((adc_t >> 4) - t1)) >> 12) * t3) >> 14;
+        let t_fine = var1 + var2;
+
+        (t_fine, (t_fine * 5 + 128) >> 8)
+    }
+
+
+    // Returns the pressure in Pa as unsigned Q24.8 fixed point number. This is
+    // the 64 bit integer implementation from the datasheet.
+    fn compensate_pressure(&self, adc_p: i32, t_fine: i32) -> u32 {
+        let mut var1 = i64::from(t_fine) - 128_000;
+        let mut var2 = var1 * var1 * i64::from(self.p6);
+        var2 += (var1 * i64::from(self.p5)) << 17;
+        var2 += i64::from(self.p4) << 35;
+        var1 = ((var1 * var1 * i64::from(self.p3)) >> 8) + ((var1 * i64::from(self.p2)) << 12);
+        var1 = (((1i64 << 47) + var1) * i64::from(self.p1)) >> 33;
+
+        if var1 == 0 {
+            // Avoid dividing by zero.
+            return 0;
+        }
+
+        let mut p = 1_048_576 - i64::from(adc_p);
+        p = (((p << 31) - var2) * 3125) / var1;
+        var1 = (i64::from(self.p9) * (p >> 13) * (p >> 13)) >> 25;
+        var2 = (i64::from(self.p8) * p) >> 19;
+        p = ((p + var1 + var2) >> 8) + (i64::from(self.p7) << 4);
+
+        p as u32
+    }
+}
+
+
+impl<I2C, E> Bmp280<I2C> where I2C: I2c<Error = E> {
+    // Identifies the device, reads its calibration and starts continuous
+    // conversions in normal mode.
+    pub fn new(i2c: I2C, address: u8) -> Result<Self, InitError<E, I2C>> {
+        let mut sensor = Bmp280{ i2c, address, chip: Chip::Bmp280, calibration: Calibration::default() };
+
+        match sensor.init() {
+            Ok(()) => Ok(sensor),
+            Err(error) => Err(InitError{ error, i2c: sensor.i2c }),
+        }
+    }
+
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+
+    pub fn chip(&self) -> Chip {
+        self.chip
+    }
+
+
+    pub fn read_measurement(&mut self) -> Result<Measurement, Error<E>> {
+        let mut data = [0u8; DATA_LEN];
+        self.i2c.write_read(self.address, &[REG_PRESS_MSB], &mut data)?;
+
+        let adc_p = adc_value(&data[0..3]);
+        let adc_t = adc_value(&data[3..6]);
+        if adc_p == ADC_RESET_VALUE || adc_t == ADC_RESET_VALUE {
+            return Err(Error::NotReady);
+        }
+
+        let (t_fine, temperature) = self.calibration.compensate_temperature(adc_t);
+        let pressure = self.calibration.compensate_pressure(adc_p, t_fine);
+
+        Ok(Measurement {
+            temperature_celsius: temperature as f32 / 100.0,
+            pressure_pa: pressure as f32 / 256.0,
+        })
+    }
+
+
+    fn init(&mut self) -> Result<(), Error<E>> {
+        let mut chip_id = [0u8; 1];
+        self.i2c.write_read(self.address, &[REG_CHIP_ID], &mut chip_id)?;
+        self.chip = match chip_id[0] {
+            CHIP_ID_BMP280 => Chip::Bmp280,
+            CHIP_ID_BME280 => Chip::Bme280,
+            id => return Err(Error::UnknownChipId(id)),
+        };
+
+        let mut calibration = [0u8; CALIBRATION_LEN];
+        self.i2c.write_read(self.address, &[REG_CALIBRATION], &mut calibration)?;
+        self.calibration = Calibration::from_registers(&calibration);
+
+        // Writes to the config register may be ignored in normal mode.
+        // Configure it while the device is still sleeping after reset.
+        self.i2c.write(self.address, &[REG_CONFIG, CONFIG_STANDBY_1000MS_FILTER_16])?;
+        self.i2c.write(self.address, &[REG_CTRL_MEAS, CTRL_MEAS_NORMAL])?;
+
+        Ok(())
+    }
+}
+
+
+
+
+// Assembles the 20 bit value from the MSB, LSB and XLSB register.
+fn adc_value(data: &[u8]) -> i32 {
+    (i32::from(data[0]) << 12) | (i32::from(data[1]) << 4) | (i32::from(data[2]) >> 4)
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal_1::i2c::{self, ErrorKind, ErrorType, NoAcknowledgeSource, Operation};
+    use super::*;
+
+
+    // A simulated BMP280 register map. Writes consisting of a single byte
+    // set the register pointer, longer ones write to registers starting at
+    // the first byte. Reads return registers starting at the pointer.
+    struct RegisterMap {
+        address: u8,
+        registers: [u8; 256],
+        pointer: u8,
+    }
+
+
+    impl RegisterMap {
+        // A BMP280 with the calibration and raw values from the example in
+        // section 8.1 of the datasheet.
+        fn datasheet_example() -> Self {
+            let mut map = RegisterMap{ address: I2C_ADDRESS_PRIMARY, registers: [0u8; 256], pointer: 0 };
+            let calibration: [u16; 12] = [
+                27504, 26435, (-1000i16) as u16,
+                36477, (-10685i16) as u16, 3024, 2855, 140, (-7i16) as u16, 15500, (-14600i16) as u16, 6000,
+            ];
+
+            map.registers[REG_CHIP_ID as usize] = CHIP_ID_BMP280;
+            for (index, value) in calibration.iter().enumerate() {
+                let offset = REG_CALIBRATION as usize + 2 * index;
+                map.registers[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+            }
+            map.set_adc(415148, 519888);
+
+            map
+        }
+
+
+        fn set_adc(&mut self, adc_p: u32, adc_t: u32) {
+            let offset = REG_PRESS_MSB as usize;
+            for (index, value) in [adc_p, adc_t].iter().enumerate() {
+                let data = [(value >> 12) as u8, (value >> 4) as u8, (value << 4) as u8];
+                self.registers[offset + 3 * index..offset + 3 * index + 3].copy_from_slice(&data);
+            }
+        }
+    }
+
+
+    #[derive(Debug, PartialEq)]
+    struct Nack;
+
+
+    impl i2c::Error for Nack {
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)
+        }
+    }
+
+
+    impl ErrorType for RegisterMap {
+        type Error = Nack;
+    }
+
+
+    impl I2c for RegisterMap {
+        fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+            if address != self.address {
+                return Err(Nack);
+            }
+
+            for operation in operations {
+                match operation {
+                    Operation::Write(data) => {
+                        self.pointer = data[0];
+                        for value in &data[1..] {
+                            self.registers[self.pointer as usize] = *value;
+                            self.pointer = self.pointer.wrapping_add(1);
+                        }
+                    }
+                    Operation::Read(buffer) => {
+                        for value in buffer.iter_mut() {
+                            *value = self.registers[self.pointer as usize];
+                            self.pointer = self.pointer.wrapping_add(1);
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+
+    #[test]
+    fn init_configures_normal_mode() {
+        let sensor = Bmp280::new(RegisterMap::datasheet_example(), I2C_ADDRESS_PRIMARY).unwrap();
+
+        assert_eq!(sensor.chip(), Chip::Bmp280);
+        let map = sensor.release();
+        assert_eq!(map.registers[REG_CTRL_MEAS as usize], CTRL_MEAS_NORMAL);
+        assert_eq!(map.registers[REG_CONFIG as usize], CONFIG_STANDBY_1000MS_FILTER_16);
+    }
+
+
+    #[test]
+    fn read_measurement_compensates_raw_values() {
+        let mut sensor = Bmp280::new(RegisterMap::datasheet_example(), I2C_ADDRESS_PRIMARY).unwrap();
+
+        let measurement = sensor.read_measurement().unwrap();
+
+        assert_eq!(measurement.temperature_celsius, 25.08);
+        assert!((measurement.pressure_pa - 100_653.27).abs() < 0.1);
+    }
+
+
+    #[test]
+    fn read_measurement_before_first_conversion() {
+        let mut map = RegisterMap::datasheet_example();
+        map.set_adc(ADC_RESET_VALUE as u32, ADC_RESET_VALUE as u32);
+        let mut sensor = Bmp280::new(map, I2C_ADDRESS_PRIMARY).unwrap();
+
+        assert_eq!(sensor.read_measurement(), Err(Error::NotReady));
+    }
+
+
+    #[test]
+    fn detects_bme280() {
+        let mut map = RegisterMap::datasheet_example();
+        map.registers[REG_CHIP_ID as usize] = CHIP_ID_BME280;
+
+        let sensor = Bmp280::new(map, I2C_ADDRESS_PRIMARY).unwrap();
+
+        assert_eq!(sensor.chip(), Chip::Bme280);
+    }
+
+
+    #[test]
+    fn rejects_unknown_chip_id() {
+        let mut map = RegisterMap::datasheet_example();
+        map.registers[REG_CHIP_ID as usize] = 0x55;
+
+        let error = Bmp280::new(map, I2C_ADDRESS_PRIMARY).err().unwrap();
+
+        assert_eq!(error.error, Error::UnknownChipId(0x55));
+        assert_eq!(error.i2c.registers[REG_CTRL_MEAS as usize], 0);
+    }
+
+
+    #[test]
+    fn reports_missing_device() {
+        let error = Bmp280::new(RegisterMap::datasheet_example(), I2C_ADDRESS_SECONDARY).err().unwrap();
+
+        assert!(matches!(error.error, Error::I2cError(Nack)));
+    }
+}
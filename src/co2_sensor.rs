@@ -0,0 +1,119 @@
+// Operations common to the CO2 sensors supported by this crate. This allows
+// applications to work with either of them while the drivers keep their
+// sensor-specific commands as inherent methods.
+//
+// Like the drivers, the traits follow the sensors' measurement modes: a
+// Co2Sensor is idle and starting measurement turns it into its
+// MeasuringCo2Sensor.
+//
+// Waiting for a measurement works the same for all sensors and gets provided
+// here on top of the drivers' data-ready status.
+
+
+use defmt::Format;
+
+use crate::scd30::{AmbientPressure, Measurement, ModeChangeError, WaitOptions};
+
+
+
+
+// The errors of a sensor as far as waiting for a measurement is concerned.
+pub trait SensorError: Format {
+    // Whether retrying might succeed.
+    fn is_transient(&self) -> bool;
+
+    // The error for no measurement becoming ready in time.
+    fn not_ready() -> Self;
+}
+
+
+
+
+pub trait Co2Sensor: Sized {
+    type Error;
+    type Measuring: MeasuringCo2Sensor<Error = Self::Error>;
+
+    // Starts measuring periodically. Passing an ambient pressure enables
+    // pressure compensation right from the start.
+    fn start_measurement(self, pressure: Option<AmbientPressure>) -> Result<Self::Measuring, ModeChangeError<Self::Error, Self>>;
+}
+
+
+pub trait MeasuringCo2Sensor {
+    type Error: SensorError;
+
+    fn is_measurement_ready(&mut self) -> Result<bool, Self::Error>;
+
+    fn read_measurement(&mut self) -> Result<Measurement, Self::Error>;
+
+    // Pauses between polling the data-ready status using the driver's delay.
+    fn pause_ms(&mut self, ms: u32);
+
+    // Polls for a new measurement and reads it as soon as it is ready.
+    // Transient errors get retried up to the given number of times. Gives up
+    // with SensorError::not_ready when no measurement became ready in time.
+    fn wait_for_measurement(&mut self, options: &WaitOptions) -> Result<Measurement, Self::Error> {
+        let mut wait = Wait::new(options);
+
+        loop {
+            let result = match self.is_measurement_ready() {
+                Ok(true) => self.read_measurement().map(Some),
+                Ok(false) => Ok(None),
+                Err(error) => Err(error),
+            };
+
+            if let Some(result) = wait.evaluate(result) {
+                return result;
+            }
+            self.pause_ms(options.poll_period_ms);
+        }
+    }
+
+    // Updates the ambient pressure used for compensation without stopping
+    // measurement.
+    fn set_ambient_pressure(&mut self, pressure: AmbientPressure) -> Result<(), Self::Error>;
+
+    // Recalibrates the sensor against a known CO2 concentration it has been
+    // measuring for a while.
+    fn set_forced_recalibration_value(&mut self, co2_ppm: u16) -> Result<(), Self::Error>;
+}
+
+
+
+// The bookkeeping for waiting for a measurement: the time waited and the
+// transient errors tolerated so far. This is shared by
+// MeasuringCo2Sensor::wait_for_measurement and the async SCD30 driver.
+pub struct Wait {
+    options: WaitOptions,
+    waited_ms: u32,
+    retries: u8,
+}
+
+
+impl Wait {
+    pub fn new(options: &WaitOptions) -> Self {
+        Wait{ options: *options, waited_ms: 0, retries: 0 }
+    }
+
+
+    // Evaluates the result of polling the sensor once: a measurement if it
+    // was ready or None if not. Returns the outcome of waiting or None if the
+    // caller should pause for the poll period and poll again.
+    pub fn evaluate<E: SensorError>(&mut self, result: Result<Option<Measurement>, E>) -> Option<Result<Measurement, E>> {
+        match result {
+            Ok(Some(measurement)) => return Some(Ok(measurement)),
+            Ok(None) => (),
+            Err(error) if error.is_transient() && self.retries < self.options.retries => {
+                defmt::debug!("retrying after transient error: {}", error);
+                self.retries += 1;
+            }
+            Err(error) => return Some(Err(error)),
+        }
+
+        if self.waited_ms >= self.options.timeout_ms {
+            return Some(Err(E::not_ready()));
+        }
+        self.waited_ms = self.waited_ms.saturating_add(self.options.poll_period_ms);
+        None
+    }
+}
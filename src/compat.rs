@@ -0,0 +1,336 @@
+// Adapters for using embedded-hal 0.2 implementations (like the TWIM and
+// UARTE from nrf52840-hal or a bus proxy from shared-bus) with the drivers
+// from this crate which are built on embedded-hal 1.0 and embedded-io.
+
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+use embedded_hal::serial;
+use embedded_hal_1::delay::DelayNs;
+use embedded_hal_1::i2c::{self, ErrorKind, ErrorType, Operation};
+
+
+
+
+// Wraps an embedded-hal 0.2 I2C implementation.
+pub struct Eh0I2c<I2C> {
+    i2c: I2C,
+}
+
+
+// Wraps an error from an embedded-hal 0.2 I2C implementation. As there is no
+// notion of error kinds there, all of them are reported as ErrorKind::Other.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Eh0I2cError<E>(pub E);
+
+
+// Wraps an embedded-hal 0.2 delay implementation.
+pub struct Eh0Delay<D> {
+    delay: D,
+}
+
+
+// Wraps the transmitting and receiving halves of an embedded-hal 0.2 serial
+// implementation (like the ones from splitting a UARTE).
+pub struct Eh0Serial<TX, RX> {
+    tx: TX,
+    rx: RX,
+    // A byte which has already been received while checking for readiness.
+    received: Option<u8>,
+}
+
+
+// Wraps an error from an embedded-hal 0.2 serial implementation. All of them
+// are reported as embedded_io::ErrorKind::Other.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Eh0SerialError<E>(pub E);
+
+
+
+
+impl<I2C> Eh0I2c<I2C> {
+    pub fn new(i2c: I2C) -> Self {
+        Eh0I2c{ i2c }
+    }
+
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+}
+
+
+impl<E: core::fmt::Debug> i2c::Error for Eh0I2cError<E> {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+
+impl<I2C, E> ErrorType for Eh0I2c<I2C>
+    where I2C: Read<Error = E> + Write<Error = E> + WriteRead<Error = E>, E: core::fmt::Debug
+{
+    type Error = Eh0I2cError<E>;
+}
+
+
+impl<I2C, E> i2c::I2c for Eh0I2c<I2C>
+    where I2C: Read<Error = E> + Write<Error = E> + WriteRead<Error = E>, E: core::fmt::Debug
+{
+    // embedded-hal 0.2 only knows about chaining a write and a read without
+    // a stop condition in between. All other operations are carried out as
+    // separate transfers.
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        let mut index = 0;
+
+        while index < operations.len() {
+            let (head, tail) = operations.split_at_mut(index + 1);
+
+            match (&mut head[index], tail.first_mut()) {
+                (Operation::Write(bytes), Some(Operation::Read(buffer))) => {
+                    self.i2c.write_read(address, bytes, buffer).map_err(Eh0I2cError)?;
+                    index += 2;
+                }
+                (Operation::Write(bytes), _) => {
+                    self.i2c.write(address, bytes).map_err(Eh0I2cError)?;
+                    index += 1;
+                }
+                (Operation::Read(buffer), _) => {
+                    self.i2c.read(address, buffer).map_err(Eh0I2cError)?;
+                    index += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+impl<D> Eh0Delay<D> {
+    pub fn new(delay: D) -> Self {
+        Eh0Delay{ delay }
+    }
+
+
+    pub fn release(self) -> D {
+        self.delay
+    }
+}
+
+
+impl<D> DelayNs for Eh0Delay<D> where D: DelayUs<u32> + DelayMs<u32> {
+    // embedded-hal 0.2 has no delays in nanoseconds. Round up to the next
+    // microsecond to wait at least as long as requested.
+    fn delay_ns(&mut self, ns: u32) {
+        self.delay.delay_us(ns.div_ceil(1_000));
+    }
+
+
+    fn delay_us(&mut self, us: u32) {
+        self.delay.delay_us(us);
+    }
+
+
+    fn delay_ms(&mut self, ms: u32) {
+        self.delay.delay_ms(ms);
+    }
+}
+
+
+
+impl<TX, RX> Eh0Serial<TX, RX> {
+    pub fn new(tx: TX, rx: RX) -> Self {
+        Eh0Serial{ tx, rx, received: None }
+    }
+
+
+    pub fn release(self) -> (TX, RX) {
+        (self.tx, self.rx)
+    }
+}
+
+
+impl<E: core::fmt::Debug> embedded_io::Error for Eh0SerialError<E> {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        embedded_io::ErrorKind::Other
+    }
+}
+
+
+impl<TX, RX, E> embedded_io::ErrorType for Eh0Serial<TX, RX>
+    where TX: serial::Write<u8, Error = E>, RX: serial::Read<u8, Error = E>, E: core::fmt::Debug
+{
+    type Error = Eh0SerialError<E>;
+}
+
+
+impl<TX, RX, E> embedded_io::Read for Eh0Serial<TX, RX>
+    where TX: serial::Write<u8, Error = E>, RX: serial::Read<u8, Error = E>, E: core::fmt::Debug
+{
+    // Blocks until a single byte has been received. There is no timeout, so
+    // a peer which does not answer at all blocks forever. Use ReadReady for
+    // waiting with a timeout.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        match buf.first_mut() {
+            Some(byte) => {
+                *byte = match self.received.take() {
+                    Some(received) => received,
+                    None => nb::block!(self.rx.read()).map_err(Eh0SerialError)?,
+                };
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+
+impl<TX, RX, E> embedded_io::ReadReady for Eh0Serial<TX, RX>
+    where TX: serial::Write<u8, Error = E>, RX: serial::Read<u8, Error = E>, E: core::fmt::Debug
+{
+    // embedded-hal 0.2 can only tell whether a byte is available by reading
+    // it. It gets kept for the next read.
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        if self.received.is_none() {
+            match self.rx.read() {
+                Ok(byte) => self.received = Some(byte),
+                Err(nb::Error::WouldBlock) => (),
+                Err(nb::Error::Other(error)) => return Err(Eh0SerialError(error)),
+            }
+        }
+
+        Ok(self.received.is_some())
+    }
+}
+
+
+impl<TX, RX, E> embedded_io::Write for Eh0Serial<TX, RX>
+    where TX: serial::Write<u8, Error = E>, RX: serial::Read<u8, Error = E>, E: core::fmt::Debug
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        for byte in buf {
+            nb::block!(self.tx.write(*byte)).map_err(Eh0SerialError)?;
+        }
+
+        Ok(buf.len())
+    }
+
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        nb::block!(self.tx.flush()).map_err(Eh0SerialError)
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_1::i2c::I2c;
+    use embedded_hal_mock::eh0::MockError;
+    use embedded_hal_mock::eh0::i2c::{Mock, Transaction};
+    use std::io::ErrorKind as IoErrorKind;
+
+
+    #[test]
+    fn single_operations() {
+        let expectations = [
+            Transaction::write(0x61, vec![0x01, 0x04]),
+            Transaction::read(0x61, vec![0x00, 0x01, 0xb0]),
+        ];
+        let mut mock = Mock::new(&expectations);
+        let mut i2c = Eh0I2c::new(mock.clone());
+
+        let mut buffer = [0u8; 3];
+        i2c.write(0x61, &[0x01, 0x04]).unwrap();
+        i2c.read(0x61, &mut buffer).unwrap();
+        assert_eq!(buffer, [0x00, 0x01, 0xb0]);
+
+        mock.done();
+    }
+
+
+    #[test]
+    fn write_followed_by_read_uses_write_read() {
+        let expectations = [
+            Transaction::write_read(0x44, vec![0xfd], vec![0x01, 0x02, 0x03]),
+            Transaction::write(0x44, vec![0x94]),
+        ];
+        let mut mock = Mock::new(&expectations);
+        let mut i2c = Eh0I2c::new(mock.clone());
+
+        let mut buffer = [0u8; 3];
+        i2c.transaction(0x44, &mut [
+            Operation::Write(&[0xfd]),
+            Operation::Read(&mut buffer),
+            Operation::Write(&[0x94]),
+        ]).unwrap();
+        assert_eq!(buffer, [0x01, 0x02, 0x03]);
+
+        mock.done();
+    }
+
+
+    #[test]
+    fn errors_get_wrapped() {
+        let expectations = [
+            Transaction::write(0x61, vec![0xd3, 0x04]).with_error(MockError::Io(IoErrorKind::Other)),
+        ];
+        let mut mock = Mock::new(&expectations);
+        let mut i2c = Eh0I2c::new(mock.clone());
+
+        let error = i2c.write(0x61, &[0xd3, 0x04]).unwrap_err();
+        assert_eq!(error, Eh0I2cError(MockError::Io(IoErrorKind::Other)));
+        assert_eq!(i2c::Error::kind(&error), ErrorKind::Other);
+
+        mock.done();
+    }
+
+
+    #[test]
+    fn serial_reads_and_writes_bytes() {
+        use embedded_hal_mock::eh0::serial;
+        use embedded_io::{Read, Write};
+
+        let expectations = [
+            serial::Transaction::write_many([0x61, 0x03]),
+            serial::Transaction::flush(),
+            serial::Transaction::read_many([0x61, 0x83]),
+        ];
+        let mut mock = serial::Mock::new(&expectations);
+        let mut uart = Eh0Serial::new(mock.clone(), mock.clone());
+
+        let mut buffer = [0u8; 2];
+        uart.write_all(&[0x61, 0x03]).unwrap();
+        uart.flush().unwrap();
+        uart.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, [0x61, 0x83]);
+
+        mock.done();
+    }
+
+
+    #[test]
+    fn serial_keeps_byte_from_checking_readiness() {
+        use embedded_hal_mock::eh0::serial;
+        use embedded_io::{Read, ReadReady};
+
+        let expectations = [
+            serial::Transaction::read_error(nb::Error::WouldBlock),
+            serial::Transaction::read_many([0x61, 0x83]),
+        ];
+        let mut mock = serial::Mock::new(&expectations);
+        let mut uart = Eh0Serial::new(mock.clone(), mock.clone());
+
+        let mut buffer = [0u8; 2];
+        assert!(!uart.read_ready().unwrap());
+        assert!(uart.read_ready().unwrap());
+        assert!(uart.read_ready().unwrap());
+        uart.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, [0x61, 0x83]);
+
+        mock.done();
+    }
+}
@@ -0,0 +1,111 @@
+// The binaries take and show a measurement once per cycle of a fixed length.
+// Displaying takes a varying amount of time, so the cycle gets kept by a
+// timer rather than by pausing for a fixed time afterwards.
+//
+// Everything sampled once per cycle depends on this length. The VOC index
+// algorithm for example needs to know its sampling interval for its time
+// constants. Deriving these from the cycle keeps them in line with the actual
+// cadence of the main loop.
+
+
+use defmt::Format;
+
+use crate::scd30::MeasurementInterval;
+use crate::sgp40::voc_index::VocIndexAlgorithm;
+
+
+
+
+#[derive(Clone, Copy, Debug, Eq, Format, PartialEq)]
+pub struct MeasurementCycle(u16);
+
+
+
+
+// The cycle of the binaries' main loop. It leaves enough time for waiting for
+// the CO2 sensor and updating an e-paper display.
+pub const MAIN_LOOP: MeasurementCycle = MeasurementCycle::from_seconds(10);
+
+
+
+
+impl MeasurementCycle {
+    pub const fn from_seconds(seconds: u16) -> Self {
+        MeasurementCycle(seconds)
+    }
+
+
+    pub fn seconds(&self) -> u16 {
+        self.0
+    }
+
+
+    // The cycle's length in ticks of a timer running at the given frequency.
+    pub fn timer_ticks(&self, ticks_per_second: u32) -> u32 {
+        u32::from(self.0) * ticks_per_second
+    }
+
+
+    // The SCD30 measurement interval for getting one measurement per cycle.
+    // This is for waiting on the sensor's data-ready pin instead of a timer.
+    // Returns None if the cycle is outside the intervals the sensor accepts.
+    pub fn scd30_measurement_interval(&self) -> Option<MeasurementInterval> {
+        MeasurementInterval::from_seconds(self.0)
+    }
+
+
+    // Creates the VOC index algorithm for sampling the SGP40 once per cycle.
+    pub fn voc_index_algorithm(&self) -> VocIndexAlgorithm {
+        VocIndexAlgorithm::with_sampling_interval(f32::from(self.0))
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::senseair_s8;
+
+
+    #[test]
+    fn timer_ticks() {
+        let cycle = MeasurementCycle::from_seconds(10);
+        assert_eq!(cycle.timer_ticks(1_000_000), 10_000_000);
+    }
+
+
+    #[test]
+    fn voc_index_algorithm_samples_at_cycle() {
+        for seconds in [1, 2, 10, 60] {
+            let cycle = MeasurementCycle::from_seconds(seconds);
+            let algorithm = cycle.voc_index_algorithm();
+            assert_eq!(algorithm.sampling_interval_s(), f32::from(seconds));
+            assert_eq!((algorithm.sampling_interval_s() * 1_000_000.0) as u32, cycle.timer_ticks(1_000_000));
+        }
+    }
+
+
+    #[test]
+    fn scd30_measurement_interval_matches_cycle() {
+        let cycle = MeasurementCycle::from_seconds(10);
+        assert_eq!(cycle.scd30_measurement_interval().map(|interval| interval.seconds()), Some(10));
+        assert_eq!(MeasurementCycle::from_seconds(1).scd30_measurement_interval(), None);
+    }
+
+
+    #[test]
+    fn main_loop_samples_voc_index_at_its_cadence() {
+        assert_eq!(MAIN_LOOP.voc_index_algorithm().sampling_interval_s(), f32::from(MAIN_LOOP.seconds()));
+        // Waiting on the data-ready pin requires the SCD30 to measure once
+        // per cycle.
+        assert!(MAIN_LOOP.scd30_measurement_interval().is_some());
+        // The SCD4x measures every 5 s and needs to deliver a new
+        // measurement within each cycle.
+        assert!(MAIN_LOOP.seconds() >= 5);
+        // The SenseAir S8 driver waits for the sensor's measurement period
+        // within each cycle.
+        assert!(MAIN_LOOP.timer_ticks(1_000) > senseair_s8::MEASUREMENT_PERIOD_MS);
+    }
+}
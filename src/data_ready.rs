@@ -0,0 +1,58 @@
+// Waiting for new measurements from the SCD30 via its data-ready (RDY) pin
+// instead of polling it over I2C. The sensor drives the pin high as soon as a
+// measurement is available and pulls it low again once it has been read.
+
+
+use cortex_m::peripheral::{NVIC, SCB};
+use embedded_hal::digital::v2::InputPin;
+use nrf52840_hal::gpio::{Input, Pin, PullDown};
+use nrf52840_hal::gpiote::Gpiote;
+use nrf52840_hal::pac::{GPIOTE, Interrupt};
+
+
+
+
+pub struct DataReadyPin {
+    gpiote: Gpiote,
+    pin: Pin<Input<PullDown>>,
+}
+
+
+
+
+impl DataReadyPin {
+    // Sets up GPIOTE channel 0 for detecting the rising edge of the pin. The
+    // GPIOTE interrupt stays disabled in the NVIC. With SEVONPEND, it becoming
+    // pending is enough for waking up the core from waiting for an event.
+    pub fn new(gpiote: GPIOTE, pin: Pin<Input<PullDown>>, scb: &mut SCB) -> Self {
+        let gpiote = Gpiote::new(gpiote);
+
+        gpiote.channel0().input_pin(&pin).lo_to_hi().enable_interrupt();
+        scb.set_sevonpend();
+
+        DataReadyPin{ gpiote, pin }
+    }
+
+
+    pub fn release(self) -> (GPIOTE, Pin<Input<PullDown>>) {
+        (self.gpiote.free(), self.pin)
+    }
+
+
+    pub fn is_measurement_ready(&self) -> bool {
+        matches!(self.pin.is_high(), Ok(true))
+    }
+
+
+    // Sleeps until the sensor signals a new measurement. Returns immediately
+    // if one is already available. Waking up for other reasons just results
+    // in checking the pin again.
+    pub fn wait(&mut self) {
+        while !self.is_measurement_ready() {
+            cortex_m::asm::wfe();
+
+            self.gpiote.channel0().reset_events();
+            NVIC::unpend(Interrupt::GPIOTE);
+        }
+    }
+}
@@ -0,0 +1,338 @@
+// Glue for running on the nRF52840 board: logging via RTT, panicking behavior
+// and memory layout. This is only needed by the binaries and would get in the
+// way of building and testing the library on the host.
+
+
+use cfg_if::cfg_if;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use defmt_rtt as _; // global logger
+use nrf52840_hal as _; // memory layout
+
+use panic_probe as _;
+
+use defmt::Format;
+use embedded_hal::timer::CountDown;
+use embedded_hal_1::delay::DelayNs;
+use embedded_hal_1::i2c::I2c;
+use nrf52840_hal::timer::{self, Periodic, Timer};
+
+use crate::bmp280::Bmp280;
+use crate::co2_sensor::{Co2Sensor, MeasuringCo2Sensor};
+use crate::cycle::MAIN_LOOP;
+use crate::scd30::{AmbientPressure, Measurement, Scd30Config, ValidMeasurement};
+#[cfg(not(feature = "data-ready-pin"))]
+use crate::scd30::WaitOptions;
+use crate::sgp40::voc_index::VocIndexAlgorithm;
+use crate::sgp40::{Compensation as VocCompensation, Sgp40};
+use crate::sht4x::{Precision, Sht4x};
+
+
+#[cfg(all(feature = "data-ready-pin", feature = "sensor-scd4x"))]
+compile_error!("the SCD4x has no data-ready pin, use either data-ready-pin or sensor-scd4x");
+#[cfg(all(feature = "data-ready-pin", feature = "sensor-senseair-s8"))]
+compile_error!("the SenseAir S8 has no data-ready pin, use either data-ready-pin or sensor-senseair-s8");
+#[cfg(all(feature = "sensor-scd4x", feature = "sensor-senseair-s8"))]
+compile_error!("the binaries support a single CO2 sensor, use either sensor-scd4x or sensor-senseair-s8");
+
+
+// same panicking *behavior* as `panic-probe` but doesn't print a panic message
+// this prevents the panic message being printed *twice* when `defmt::panic` is invoked
+#[defmt::panic_handler]
+fn panic() -> ! {
+    cortex_m::asm::udf()
+}
+
+defmt::timestamp!("{=usize}", {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    // NOTE(no-CAS) `timestamps` runs with interrupts disabled
+    let n = COUNT.load(Ordering::Relaxed);
+    COUNT.store(n + 1, Ordering::Relaxed);
+    n
+});
+
+// The SCD30 settings shared by all binaries. The ambient pressure for
+// compensation comes from the barometer at runtime. When waiting on its
+// data-ready pin, the sensor paces the main loop and measures once per cycle.
+pub fn scd30_config() -> Scd30Config {
+    cfg_if! {
+        if #[cfg(feature = "data-ready-pin")] {
+            let measurement_interval = defmt::unwrap!(MAIN_LOOP.scd30_measurement_interval());
+            Scd30Config{ measurement_interval, ..Scd30Config::default() }
+        } else {
+            Scd30Config::default()
+        }
+    }
+}
+
+
+// Starts keeping the main loop's cycle with the given timer.
+pub fn start_measurement_cycle<T: timer::Instance>(timer: Timer<T>) -> Timer<T, Periodic> {
+    let mut timer = timer.into_periodic();
+    timer.start(MAIN_LOOP.timer_ticks(Timer::<T>::TICKS_PER_SECOND));
+    timer
+}
+
+
+// Waits for the rest of the current cycle. A cycle overrun by the loop gets
+// reported and the next one starts right away.
+pub fn wait_for_next_cycle<T: timer::Instance>(timer: &mut Timer<T, Periodic>) {
+    if timer.wait().is_ok() {
+        defmt::warn!("main loop overran its cycle of {=u16} s", MAIN_LOOP.seconds());
+        return;
+    }
+
+    match nb::block!(timer.wait()) {
+        Ok(()) => (),
+        Err(never) => match never {},
+    }
+}
+
+
+// The VOC sensor gets sampled once per cycle of the main loop.
+pub fn voc_index_algorithm() -> VocIndexAlgorithm {
+    MAIN_LOOP.voc_index_algorithm()
+}
+
+cfg_if! {
+    if #[cfg(feature = "sensor-senseair-s8")] {
+        use embedded_io::{Read, ReadReady, Write};
+        use nrf52840_hal::gpio::{Floating, Input, Output, Pin, PushPull};
+        use nrf52840_hal::pac::UARTE0;
+        use nrf52840_hal::uarte::{self, Uarte, UarteRx, UarteTx};
+
+        use crate::compat::Eh0Serial;
+        use crate::senseair_s8::SenseairS8;
+
+        // Sets up UARTE0 for talking to the S8.
+        pub fn co2_sensor_uart(uarte: UARTE0, rxd: Pin<Input<Floating>>, txd: Pin<Output<PushPull>>) -> Eh0Serial<UarteTx<UARTE0>, UarteRx<UARTE0>> {
+            let pins = uarte::Pins{ rxd, txd, cts: None, rts: None };
+            let uarte = Uarte::new(uarte, pins, uarte::Parity::EXCLUDED, uarte::Baudrate::BAUD9600);
+            let tx_buffer = cortex_m::singleton!(: [u8; 16] = [0; 16]).unwrap();
+            let rx_buffer = cortex_m::singleton!(: [u8; 1] = [0; 1]).unwrap();
+            let (tx, rx) = uarte.split(tx_buffer, rx_buffer).expect("splitting UARTE failed");
+
+            Eh0Serial::new(tx, rx)
+        }
+
+        // The S8 measures right from power-up. It has no pressure
+        // compensation, so the pressure only gets logged.
+        pub fn start_co2_sensor<UART, D>(uart: UART, delay: D, pressure: Option<AmbientPressure>) -> impl MeasuringCo2Sensor<Error = impl Format> where UART: Read + ReadReady + Write, D: DelayNs {
+            let mut sensor = SenseairS8::new(uart, delay);
+
+            let sensor_id = defmt::unwrap!(sensor.get_sensor_id());
+            defmt::info!("SenseAir S8 sensor ID: {=u32:#x}", sensor_id);
+            let firmware_version = defmt::unwrap!(sensor.get_firmware_version());
+            defmt::info!("SenseAir S8 firmware version: {}", firmware_version);
+            let abc_active = defmt::unwrap!(sensor.get_automatic_baseline_correction());
+            defmt::info!("SenseAir S8 automatic baseline correction active: {=bool}", abc_active);
+            defmt::info!("ambient pressure (not compensated): {}", pressure);
+
+            defmt::unwrap!(sensor.start_measurement(pressure))
+        }
+
+        // The driver considers a new reading ready after waiting for the
+        // sensor's measurement period. The default timeout leaves room for
+        // this.
+        #[cfg(not(feature = "data-ready-pin"))]
+        pub fn co2_wait_options() -> WaitOptions {
+            WaitOptions::default()
+        }
+    } else if #[cfg(feature = "sensor-scd4x")] {
+        // Brings the SCD4x into a known state and starts periodic
+        // measurement.
+        pub fn start_co2_sensor<I2C, D>(i2c: I2C, delay: D, pressure: Option<AmbientPressure>) -> impl MeasuringCo2Sensor<Error = impl Format> where I2C: I2c, D: DelayNs {
+            let sensor = crate::scd4x::Scd4x::new(i2c, delay);
+            let mut sensor = defmt::unwrap!(sensor.stop_periodic_measurement());
+
+            let serial_number = defmt::unwrap!(sensor.get_serial_number());
+            defmt::info!("SCD4x serial number: {=u64:#x}", serial_number);
+            let asc_active = defmt::unwrap!(sensor.get_automatic_self_calibration());
+            defmt::info!("SCD4x automatic self-calibration active: {=bool}", asc_active);
+            defmt::info!("ambient pressure: {}", pressure);
+
+            defmt::unwrap!(sensor.start_measurement(pressure))
+        }
+
+        // Suits periodic measurement every 5 s.
+        #[cfg(not(feature = "data-ready-pin"))]
+        pub fn co2_wait_options() -> WaitOptions {
+            WaitOptions{ poll_period_ms: 500, timeout_ms: 6_000, retries: 3 }
+        }
+    } else {
+        // Applies the shared configuration to the SCD30 and starts continuous
+        // measurement.
+        pub fn start_co2_sensor<I2C, D>(i2c: I2C, delay: D, pressure: Option<AmbientPressure>) -> impl MeasuringCo2Sensor<Error = impl Format> where I2C: I2c, D: DelayNs {
+            let mut sensor = crate::scd30::Scd30::new(i2c, delay);
+
+            let firmware_version = defmt::unwrap!(sensor.get_firmware_version());
+            defmt::info!("SCD30 firmware version: {:?}", firmware_version);
+            let asc_active = defmt::unwrap!(sensor.get_automatic_self_calibration());
+            defmt::info!("SCD30 automatic self-calibration active: {=bool}", asc_active);
+            let config = scd30_config();
+            defmt::info!("SCD30 configuration: {}", config);
+            defmt::unwrap!(sensor.configure(&config));
+            let pressure = pressure.or(config.pressure());
+            defmt::info!("ambient pressure: {}", pressure);
+
+            defmt::unwrap!(sensor.start_measurement(pressure))
+        }
+
+        #[cfg(not(feature = "data-ready-pin"))]
+        pub fn co2_wait_options() -> WaitOptions {
+            WaitOptions::default()
+        }
+    }
+}
+
+// Reads the ambient pressure for compensating the CO2 sensor from the
+// barometer.
+pub fn read_ambient_pressure<I2C: I2c>(barometer: &mut Bmp280<I2C>) -> Option<AmbientPressure> {
+    let measurement = barometer.read_measurement()
+        .map_err(|error| defmt::warn!("reading barometer failed: {}", error))
+        .ok()?;
+    let pressure = AmbientPressure::from_pascal(measurement.pressure_pa);
+    if pressure.is_none() {
+        defmt::warn!("ignoring ambient pressure out of range: {=f32} Pa", measurement.pressure_pa);
+    }
+
+    pressure
+}
+
+// Feeds the pressure from the barometer into the ambient pressure
+// compensation of the CO2 sensor. The sensor only gets updated when the
+// pressure changed from the one passed last.
+pub fn update_ambient_pressure<S, B>(sensor: &mut S, barometer: &mut Bmp280<B>, current: &mut Option<AmbientPressure>) where S: MeasuringCo2Sensor, S::Error: Format, B: I2c {
+    let pressure = match read_ambient_pressure(barometer) {
+        Some(pressure) if Some(pressure) != *current => pressure,
+        _ => return,
+    };
+
+    match sensor.set_ambient_pressure(pressure) {
+        Ok(()) => {
+            defmt::info!("ambient pressure: {}", pressure);
+            *current = Some(pressure);
+        }
+        Err(error) => defmt::warn!("setting ambient pressure failed: {}", error),
+    }
+}
+
+// Returns the SHT4x for cross-checking temperature and humidity if there is
+// one on the bus.
+pub fn probe_reference_sensor<I2C, D>(i2c: I2C, delay: D) -> Option<Sht4x<I2C, D>> where I2C: I2c, D: DelayNs {
+    let mut reference = Sht4x::new(i2c, delay);
+
+    match reference.read_serial_number() {
+        Ok(serial_number) => {
+            defmt::info!("SHT4x serial number: {=u32:#x}", serial_number);
+            Some(reference)
+        }
+        Err(error) => {
+            defmt::info!("no SHT4x reference sensor: {}", error);
+            None
+        }
+    }
+}
+
+// Reports how far temperature and humidity from the CO2 sensor deviate from
+// the reference sensor.
+pub fn cross_check<I2C, D>(reference: &mut Sht4x<I2C, D>, measurement: &Measurement) where I2C: I2c, D: DelayNs {
+    match reference.measure(Precision::High) {
+        Ok(reference) => match reference.deviation(measurement) {
+            Some(deviation) => defmt::info!("deviation from reference: {}", deviation),
+            None => defmt::debug!("no temperature and humidity for comparing with reference"),
+        },
+        Err(error) => defmt::warn!("reading reference sensor failed: {}", error),
+    }
+}
+
+// Returns the SGP40 for measuring VOCs if there is one on the bus.
+pub fn probe_voc_sensor<I2C, D>(i2c: I2C, delay: D) -> Option<Sgp40<I2C, D>> where I2C: I2c, D: DelayNs {
+    let mut sensor = Sgp40::new(i2c, delay);
+
+    match sensor.get_serial_number() {
+        Ok(serial_number) => {
+            defmt::info!("SGP40 serial number: {=u64:#x}", serial_number);
+            Some(sensor)
+        }
+        Err(error) => {
+            defmt::info!("no SGP40 VOC sensor: {}", error);
+            None
+        }
+    }
+}
+
+// Measures the raw VOC signal compensated with temperature and humidity from
+// the CO2 sensor and feeds it into the VOC index algorithm. This needs to
+// happen once per cycle, even without a valid CO2 measurement. The sensor's
+// defaults stand in for its temperature and humidity then.
+pub fn update_voc_index<I2C, D>(sensor: &mut Sgp40<I2C, D>, algorithm: &mut VocIndexAlgorithm, measurement: Option<&Measurement>) where I2C: I2c, D: DelayNs {
+    let compensation = measurement.map(VocCompensation::from).unwrap_or_default();
+
+    match sensor.measure_raw_signal(&compensation) {
+        Ok(sraw) => defmt::info!("VOC index: {=u16} (raw signal {=u16})", algorithm.process(sraw), sraw),
+        Err(error) => defmt::warn!("reading VOC sensor failed: {}", error),
+    }
+}
+
+// Reads the CO2 measurement of the current cycle. When waiting on the SCD30's
+// data-ready pin, the main loop only starts a cycle once the measurement is
+// available. Otherwise this polls the sensor until it is.
+fn read_co2_measurement<S: MeasuringCo2Sensor>(sensor: &mut S) -> Result<Measurement, S::Error> {
+    cfg_if! {
+        if #[cfg(feature = "data-ready-pin")] {
+            sensor.read_measurement()
+        } else {
+            sensor.wait_for_measurement(&co2_wait_options())
+        }
+    }
+}
+
+// Takes the measurements of one cycle of the main loop: feeds the pressure
+// from the barometer into the CO2 sensor, reads its measurement and
+// validates it, samples the VOC sensor and cross-checks with the reference
+// sensor. Returns the CO2 measurement for displaying if there is a valid one.
+// Errors get reported but don't stop the main loop.
+pub fn measure<S, B, R, RD, V, VD>(
+    sensor: &mut S,
+    barometer: Option<&mut Bmp280<B>>,
+    pressure: &mut Option<AmbientPressure>,
+    reference: Option<&mut Sht4x<R, RD>>,
+    voc_sensor: Option<&mut Sgp40<V, VD>>,
+    voc_index: &mut VocIndexAlgorithm,
+) -> Option<ValidMeasurement> where S: MeasuringCo2Sensor, B: I2c, R: I2c, RD: DelayNs, V: I2c, VD: DelayNs {
+    if let Some(barometer) = barometer {
+        update_ambient_pressure(sensor, barometer, pressure);
+    }
+
+    let measurement = read_co2_measurement(sensor)
+        .map_err(|error| defmt::warn!("reading measurement failed: {}", error))
+        .ok();
+    let measurement = measurement.and_then(|measurement| match measurement.validate() {
+        Ok(measurement) => Some(measurement),
+        Err(reason) => {
+            defmt::warn!("discarding implausible measurement {}: {}", measurement, reason);
+            None
+        }
+    });
+
+    if let Some(voc_sensor) = voc_sensor {
+        update_voc_index(voc_sensor, voc_index, measurement.as_ref().map(|measurement| measurement.measurement()));
+    }
+
+    if let Some(measurement) = measurement.as_ref() {
+        defmt::info!("measurement: {:?}", measurement);
+        if let Some(reference) = reference {
+            cross_check(reference, measurement.measurement());
+        }
+    }
+
+    measurement
+}
+
+/// Terminates the application and makes `probe-run` exit with exit-code = 0
+pub fn exit() -> ! {
+    loop {
+        cortex_m::asm::bkpt();
+    }
+}
@@ -1,34 +1,40 @@
-#![no_std]
-
-use core::sync::atomic::{AtomicUsize, Ordering};
-
-use defmt_rtt as _; // global logger
-use nrf52840_hal as _; // memory layout
-
-use panic_probe as _;
-
-
+#![cfg_attr(not(test), no_std)]
+
+
+pub mod bmp280;
+pub mod co2_sensor;
+#[cfg(feature = "eh0")]
+pub mod compat;
+pub mod cycle;
+#[cfg(feature = "firmware")]
+pub mod data_ready;
+#[cfg(feature = "firmware")]
+mod firmware;
+pub mod modbus;
 pub mod scd30;
-
-
-// same panicking *behavior* as `panic-probe` but doesn't print a panic message
-// this prevents the panic message being printed *twice* when `defmt::panic` is invoked
-#[defmt::panic_handler]
-fn panic() -> ! {
-    cortex_m::asm::udf()
-}
-
-defmt::timestamp!("{=usize}", {
-    static COUNT: AtomicUsize = AtomicUsize::new(0);
-    // NOTE(no-CAS) `timestamps` runs with interrupts disabled
-    let n = COUNT.load(Ordering::Relaxed);
-    COUNT.store(n + 1, Ordering::Relaxed);
-    n as usize
-});
-
-/// Terminates the application and makes `probe-run` exit with exit-code = 0
-pub fn exit() -> ! {
-    loop {
-        cortex_m::asm::bkpt();
-    }
-}
+pub mod scd4x;
+pub mod senseair_s8;
+pub mod sensirion;
+pub mod sgp40;
+pub mod sht4x;
+pub mod sps30;
+#[cfg(test)]
+mod testing;
+pub mod ui;
+
+
+#[cfg(feature = "firmware")]
+pub use firmware::{
+    exit,
+    measure,
+    probe_reference_sensor,
+    probe_voc_sensor,
+    read_ambient_pressure,
+    scd30_config,
+    start_co2_sensor,
+    start_measurement_cycle,
+    voc_index_algorithm,
+    wait_for_next_cycle,
+};
+#[cfg(feature = "sensor-senseair-s8")]
+pub use firmware::co2_sensor_uart;
@@ -0,0 +1,608 @@
+// A minimal Modbus RTU client for talking to sensors over a UART. It covers
+// the function codes used by the sensors in this crate: reading holding and
+// input registers and writing a single register.
+//
+// See 'MODBUS over Serial Line Specification and Implementation Guide' and
+// 'MODBUS Application Protocol Specification' for the details.
+
+
+use embedded_hal_1::delay::DelayNs;
+use embedded_io::{Read, ReadReady, Write};
+
+
+
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error<E> {
+    // The CRC16 of a response frame did not match its content.
+    CrcError,
+    // The device answered with an exception response. Carries the exception
+    // code.
+    Exception(u8),
+    // The response did not match the request (wrong device address, function
+    // code or length).
+    UnexpectedResponse,
+    // The requested number of registers does not fit into a single frame.
+    TooManyRegisters,
+    // The device did not start responding within the response timeout or
+    // fell silent in the middle of a frame.
+    Timeout,
+    UartError(E),
+}
+
+
+pub struct ModbusRtu<UART, D> {
+    uart: UART,
+    delay: D,
+    character_us: u32,
+    silence_us: u32,
+}
+
+
+
+
+pub const FUNCTION_READ_HOLDING_REGISTERS: u8 = 0x03;
+pub const FUNCTION_READ_INPUT_REGISTERS: u8 = 0x04;
+pub const FUNCTION_WRITE_SINGLE_REGISTER: u8 = 0x06;
+
+// The maximum number of registers which can be read with a single request
+// (the byte count of a response is limited to 250 bytes).
+pub const MAX_REGISTERS: usize = 125;
+
+// Responses with the highest bit of the function code set are exception
+// responses.
+const EXCEPTION_FLAG: u8 = 0x80;
+
+// How long a device may take before it starts responding. Once it has, the
+// bytes of a frame must follow each other without a silent interval.
+pub const RESPONSE_TIMEOUT_US: u32 = 1_000_000;
+// The maximum length of a frame on a serial line. This bounds discarding the
+// rest of a rejected response.
+const MAX_FRAME_LEN: usize = 256;
+
+
+
+
+// Allow automatic conversion from the UART's error type to the client's error
+// type (for the question mark operator).
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::UartError(err)
+    }
+}
+
+
+impl<UART, D, E> ModbusRtu<UART, D> where UART: Read<Error = E> + ReadReady<Error = E> + Write<Error = E>, D: DelayNs {
+    // The baud rate is used for determining the silent interval of 3.5
+    // character times required between two frames. The delay paces polling
+    // the UART for responses.
+    pub fn new(uart: UART, delay: D, baud_rate: u32) -> Self {
+        ModbusRtu {
+            uart,
+            delay,
+            character_us: character_time_us(baud_rate),
+            silence_us: silent_interval_us(baud_rate),
+        }
+    }
+
+
+    // Gives back the UART and the delay.
+    pub fn release(self) -> (UART, D) {
+        (self.uart, self.delay)
+    }
+
+
+    // Lends the delay to devices which need to pause between requests for
+    // longer than the silent interval.
+    pub fn delay(&mut self) -> &mut D {
+        &mut self.delay
+    }
+
+
+    pub fn read_holding_registers(&mut self, device: u8, address: u16, registers: &mut [u16]) -> Result<(), Error<E>> {
+        self.read_registers(device, FUNCTION_READ_HOLDING_REGISTERS, address, registers)
+    }
+
+
+    pub fn read_input_registers(&mut self, device: u8, address: u16, registers: &mut [u16]) -> Result<(), Error<E>> {
+        self.read_registers(device, FUNCTION_READ_INPUT_REGISTERS, address, registers)
+    }
+
+
+    pub fn write_single_register(&mut self, device: u8, address: u16, value: u16) -> Result<(), Error<E>> {
+        let request = request_frame(device, FUNCTION_WRITE_SINGLE_REGISTER, address, value);
+        self.send(&request)?;
+
+        let result = self.receive_echo(device, &request);
+        self.discard_rejected(result)
+    }
+
+
+    fn read_registers(&mut self, device: u8, function: u8, address: u16, registers: &mut [u16]) -> Result<(), Error<E>> {
+        if registers.len() > MAX_REGISTERS {
+            return Err(Error::TooManyRegisters);
+        }
+
+        let request = request_frame(device, function, address, registers.len() as u16);
+        self.send(&request)?;
+
+        let result = self.receive_registers(device, function, registers);
+        self.discard_rejected(result)
+    }
+
+
+    // A successful write gets acknowledged by echoing the request.
+    fn receive_echo(&mut self, device: u8, request: &[u8; 8]) -> Result<(), Error<E>> {
+        let mut response = [0u8; 8];
+        self.receive_header(device, FUNCTION_WRITE_SINGLE_REGISTER, &mut response)?;
+        self.receive_rest(&mut response[2..])?;
+        defmt::trace!("response: {=[u8]}", response);
+
+        check_crc(&response)?;
+        if response == *request {
+            Ok(())
+        } else {
+            Err(Error::UnexpectedResponse)
+        }
+    }
+
+
+    fn receive_registers(&mut self, device: u8, function: u8, registers: &mut [u16]) -> Result<(), Error<E>> {
+        // Device address, function code, byte count, data and CRC.
+        let mut response = [0u8; 3 + 2 * MAX_REGISTERS + 2];
+        let byte_count = 2 * registers.len();
+        let response = &mut response[..3 + byte_count + 2];
+
+        self.receive_header(device, function, response)?;
+        self.receive_rest(&mut response[2..])?;
+        defmt::trace!("response: {=[u8]}", response);
+
+        check_crc(response)?;
+        if response[2] as usize != byte_count {
+            return Err(Error::UnexpectedResponse);
+        }
+
+        for (register, data) in registers.iter_mut().zip(response[3..3 + byte_count].chunks_exact(2)) {
+            *register = u16::from_be_bytes([data[0], data[1]]);
+        }
+
+        Ok(())
+    }
+
+
+    fn send(&mut self, request: &[u8]) -> Result<(), Error<E>> {
+        defmt::trace!("request: {=[u8]}", request);
+
+        self.delay.delay_us(self.silence_us);
+        self.uart.write_all(request)?;
+        self.uart.flush()?;
+
+        Ok(())
+    }
+
+
+    // Reads device address and function code into the first two bytes of
+    // the given buffer and handles exception responses.
+    fn receive_header(&mut self, device: u8, function: u8, response: &mut [u8]) -> Result<(), Error<E>> {
+        response[0] = self.receive_byte(RESPONSE_TIMEOUT_US)?;
+        self.receive_rest(&mut response[1..2])?;
+
+        if response[1] == function | EXCEPTION_FLAG {
+            let mut exception = [response[0], response[1], 0, 0, 0];
+            self.receive_rest(&mut exception[2..])?;
+            defmt::trace!("exception response: {=[u8]}", exception);
+
+            check_crc(&exception)?;
+            return Err(Error::Exception(exception[2]));
+        }
+
+        if response[0] != device || response[1] != function {
+            return Err(Error::UnexpectedResponse);
+        }
+
+        Ok(())
+    }
+
+
+    // Receives the remaining bytes of a frame which has already started.
+    fn receive_rest(&mut self, buffer: &mut [u8]) -> Result<(), Error<E>> {
+        for byte in buffer.iter_mut() {
+            *byte = self.receive_byte(self.silence_us)?;
+        }
+
+        Ok(())
+    }
+
+
+    // Polls the UART once per character time until a byte has been received
+    // or the timeout has passed.
+    fn receive_byte(&mut self, timeout_us: u32) -> Result<u8, Error<E>> {
+        let mut waited_us = 0;
+        while !self.uart.read_ready()? {
+            if waited_us >= timeout_us {
+                return Err(Error::Timeout);
+            }
+            self.delay.delay_us(self.character_us);
+            waited_us += self.character_us;
+        }
+
+        let mut byte = [0u8];
+        self.uart.read_exact(&mut byte).map_err(read_exact_error)?;
+        Ok(byte[0])
+    }
+
+
+    // Drops what is left of a response which got rejected before it has
+    // been read completely. It would be taken for the start of the next
+    // response otherwise. The frame ends once the line falls silent.
+    fn discard_rejected<T>(&mut self, result: Result<T, Error<E>>) -> Result<T, Error<E>> {
+        if let Err(Error::CrcError | Error::UnexpectedResponse) = result {
+            for _ in 0..MAX_FRAME_LEN {
+                match self.receive_byte(self.silence_us) {
+                    Ok(byte) => defmt::trace!("discarding: {=u8:#04x}", byte),
+                    Err(Error::Timeout) => break,
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+
+        result
+    }
+}
+
+
+
+
+// The CRC16 used by Modbus RTU. It gets transmitted low byte first.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            if crc & 0x0001 != 0 {
+                crc = (crc >> 1) ^ 0xa001;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+
+    crc
+}
+
+
+// Builds a request frame consisting of the device address, function code,
+// two 16 bit values and the CRC. This covers reading registers (start address
+// and register count) and writing a single register (address and value).
+pub fn request_frame(device: u8, function: u8, address: u16, value: u16) -> [u8; 8] {
+    let mut frame = [0u8; 8];
+
+    frame[0] = device;
+    frame[1] = function;
+    frame[2..4].copy_from_slice(&address.to_be_bytes());
+    frame[4..6].copy_from_slice(&value.to_be_bytes());
+    let crc = crc16(&frame[0..6]);
+    frame[6..8].copy_from_slice(&crc.to_le_bytes());
+
+    frame
+}
+
+
+fn check_crc<E>(frame: &[u8]) -> Result<(), Error<E>> {
+    let (data, crc) = frame.split_at(frame.len() - 2);
+
+    if crc16(data).to_le_bytes() == crc {
+        Ok(())
+    } else {
+        Err(Error::CrcError)
+    }
+}
+
+
+// A frame consists of 11 bits per character (start bit, eight data bits,
+// parity or a second stop bit and a stop bit).
+fn character_time_us(baud_rate: u32) -> u32 {
+    11 * 1_000_000 / baud_rate + 1
+}
+
+
+// The specification recommends a fixed silent interval of 1750 us for baud
+// rates above 19200.
+fn silent_interval_us(baud_rate: u32) -> u32 {
+    if baud_rate > 19_200 {
+        1_750
+    } else {
+        (35 * 11 * 1_000_000 / 10) / baud_rate + 1
+    }
+}
+
+
+// A UART which runs dry in the middle of a frame is reported as an
+// unexpected response.
+fn read_exact_error<E>(error: embedded_io::ReadExactError<E>) -> Error<E> {
+    match error {
+        embedded_io::ReadExactError::UnexpectedEof => Error::UnexpectedResponse,
+        embedded_io::ReadExactError::Other(error) => Error::UartError(error),
+    }
+}
+
+
+
+
+// A simulated serial peer for testing Modbus devices. It expects requests in
+// the order they are scripted and answers each with its scripted response.
+#[cfg(test)]
+pub(crate) mod testing {
+    use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write};
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+
+    pub struct ScriptedPeer {
+        exchanges: VecDeque<(Vec<u8>, Vec<u8>)>,
+        request: Vec<u8>,
+        response: VecDeque<u8>,
+    }
+
+
+    impl ScriptedPeer {
+        pub fn new(exchanges: &[(&[u8], &[u8])]) -> Self {
+            let exchanges = exchanges.iter()
+                .map(|(request, response)| (request.to_vec(), response.to_vec()))
+                .collect();
+            ScriptedPeer{ exchanges, request: Vec::new(), response: VecDeque::new() }
+        }
+
+
+        // Checks that all scripted exchanges took place.
+        pub fn done(&self) {
+            assert!(self.exchanges.is_empty(), "pending exchanges: {:x?}", self.exchanges);
+            assert!(self.request.is_empty(), "incomplete request: {:x?}", self.request);
+        }
+    }
+
+
+    impl ErrorType for ScriptedPeer {
+        type Error = ErrorKind;
+    }
+
+
+    impl Write for ScriptedPeer {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.request.extend_from_slice(buf);
+
+            let (expected, _) = self.exchanges.front().expect("unexpected request");
+            assert!(expected.starts_with(&self.request), "unexpected request: {:x?}, expected: {:x?}",
+                self.request, expected);
+
+            if self.request.len() == expected.len() {
+                let (_, response) = self.exchanges.pop_front().unwrap();
+                self.response.extend(response);
+                self.request.clear();
+            }
+
+            Ok(buf.len())
+        }
+
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+
+    // A silent peer is never ready. This lets the client run into its
+    // timeout.
+    impl ReadReady for ScriptedPeer {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            Ok(!self.response.is_empty())
+        }
+    }
+
+
+    impl Read for ScriptedPeer {
+        // Reports an error instead of blocking when there is nothing left to
+        // receive.
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            if self.response.is_empty() {
+                return Err(ErrorKind::TimedOut);
+            }
+
+            let count = buf.len().min(self.response.len());
+            for byte in buf[..count].iter_mut() {
+                *byte = self.response.pop_front().unwrap();
+            }
+
+            Ok(count)
+        }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::testing::ScriptedPeer;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+
+    #[test]
+    fn crc16_matches_scd30_example() {
+        // 'Get data ready status' example from the SCD30 interface
+        // description.
+        assert_eq!(request_frame(0x61, 0x03, 0x0027, 0x0001), [0x61, 0x03, 0x00, 0x27, 0x00, 0x01, 0x3d, 0xa1]);
+    }
+
+
+    #[test]
+    fn silent_interval() {
+        assert_eq!(silent_interval_us(9_600), 4_011);
+        assert_eq!(silent_interval_us(19_200), 2_006);
+        assert_eq!(silent_interval_us(115_200), 1_750);
+    }
+
+
+    #[test]
+    fn read_holding_registers() {
+        let mut peer = ScriptedPeer::new(&[(
+            &[0x61, 0x03, 0x00, 0x27, 0x00, 0x01, 0x3d, 0xa1],
+            &[0x61, 0x03, 0x02, 0x00, 0x01, 0xf9, 0x8c],
+        )]);
+        let mut registers = [0u16; 1];
+
+        let mut modbus = ModbusRtu::new(&mut peer, NoopDelay::new(), 19_200);
+        modbus.read_holding_registers(0x61, 0x0027, &mut registers).unwrap();
+        assert_eq!(registers, [0x0001]);
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn write_single_register() {
+        let mut peer = ScriptedPeer::new(&[(
+            &[0x61, 0x06, 0x00, 0x37, 0x00, 0x01, 0xf0, 0x64],
+            &[0x61, 0x06, 0x00, 0x37, 0x00, 0x01, 0xf0, 0x64],
+        )]);
+
+        let mut modbus = ModbusRtu::new(&mut peer, NoopDelay::new(), 19_200);
+        modbus.write_single_register(0x61, 0x0037, 0x0001).unwrap();
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn detects_crc_error() {
+        let mut peer = ScriptedPeer::new(&[(
+            &[0x61, 0x03, 0x00, 0x27, 0x00, 0x01, 0x3d, 0xa1],
+            &[0x61, 0x03, 0x02, 0x00, 0x01, 0xf9, 0x8d],
+        )]);
+        let mut registers = [0u16; 1];
+
+        let mut modbus = ModbusRtu::new(&mut peer, NoopDelay::new(), 19_200);
+        assert_eq!(modbus.read_holding_registers(0x61, 0x0027, &mut registers), Err(Error::CrcError));
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn reports_exception() {
+        // Illegal data address.
+        let mut peer = ScriptedPeer::new(&[(
+            &[0x61, 0x03, 0x00, 0x27, 0x00, 0x01, 0x3d, 0xa1],
+            &[0x61, 0x83, 0x02, 0xc0, 0xef],
+        )]);
+        let mut registers = [0u16; 1];
+
+        let mut modbus = ModbusRtu::new(&mut peer, NoopDelay::new(), 19_200);
+        assert_eq!(modbus.read_holding_registers(0x61, 0x0027, &mut registers), Err(Error::Exception(0x02)));
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn detects_unexpected_response() {
+        let mut peer = ScriptedPeer::new(&[(
+            &[0x61, 0x03, 0x00, 0x27, 0x00, 0x01, 0x3d, 0xa1],
+            &[0x62, 0x03, 0x02, 0x00, 0x01, 0xf9, 0x8c],
+        )]);
+        let mut registers = [0u16; 1];
+
+        let mut modbus = ModbusRtu::new(&mut peer, NoopDelay::new(), 19_200);
+        assert_eq!(modbus.read_holding_registers(0x61, 0x0027, &mut registers), Err(Error::UnexpectedResponse));
+    }
+
+
+    #[test]
+    fn discards_rest_of_unexpected_response() {
+        let mut peer = ScriptedPeer::new(&[
+            (
+                &[0x61, 0x03, 0x00, 0x27, 0x00, 0x01, 0x3d, 0xa1],
+                &[0x62, 0x03, 0x02, 0x00, 0x01, 0xf9, 0x8c],
+            ),
+            (
+                &[0x61, 0x03, 0x00, 0x27, 0x00, 0x01, 0x3d, 0xa1],
+                &[0x61, 0x03, 0x02, 0x00, 0x01, 0xf9, 0x8c],
+            ),
+        ]);
+        let mut registers = [0u16; 1];
+
+        let mut modbus = ModbusRtu::new(&mut peer, NoopDelay::new(), 19_200);
+        assert_eq!(modbus.read_holding_registers(0x61, 0x0027, &mut registers), Err(Error::UnexpectedResponse));
+        modbus.read_holding_registers(0x61, 0x0027, &mut registers).unwrap();
+        assert_eq!(registers, [0x0001]);
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn discards_rest_of_response_with_wrong_byte_count() {
+        let mut peer = ScriptedPeer::new(&[
+            (
+                &[0x61, 0x03, 0x00, 0x27, 0x00, 0x01, 0x3d, 0xa1],
+                &[0x61, 0x03, 0x04, 0x00, 0x01, 0x00, 0x02, 0x4a, 0x34],
+            ),
+            (
+                &[0x61, 0x06, 0x00, 0x37, 0x00, 0x01, 0xf0, 0x64],
+                &[0x61, 0x06, 0x00, 0x37, 0x00, 0x01, 0xf0, 0x64],
+            ),
+        ]);
+        let mut registers = [0u16; 1];
+
+        let mut modbus = ModbusRtu::new(&mut peer, NoopDelay::new(), 19_200);
+        // The CRC gets checked over the expected length of the response.
+        assert_eq!(modbus.read_holding_registers(0x61, 0x0027, &mut registers), Err(Error::CrcError));
+        modbus.write_single_register(0x61, 0x0037, 0x0001).unwrap();
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn reports_timeout_for_silent_device() {
+        let mut peer = ScriptedPeer::new(&[(
+            &[0x61, 0x03, 0x00, 0x27, 0x00, 0x01, 0x3d, 0xa1],
+            &[],
+        )]);
+        let mut registers = [0u16; 1];
+
+        let mut modbus = ModbusRtu::new(&mut peer, NoopDelay::new(), 19_200);
+        assert_eq!(modbus.read_holding_registers(0x61, 0x0027, &mut registers), Err(Error::Timeout));
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn reports_timeout_for_truncated_response() {
+        let mut peer = ScriptedPeer::new(&[(
+            &[0x61, 0x03, 0x00, 0x27, 0x00, 0x01, 0x3d, 0xa1],
+            &[0x61, 0x03, 0x02, 0x00],
+        )]);
+        let mut registers = [0u16; 1];
+
+        let mut modbus = ModbusRtu::new(&mut peer, NoopDelay::new(), 19_200);
+        assert_eq!(modbus.read_holding_registers(0x61, 0x0027, &mut registers), Err(Error::Timeout));
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn rejects_too_many_registers() {
+        let mut peer = ScriptedPeer::new(&[]);
+        let mut registers = [0u16; MAX_REGISTERS + 1];
+
+        let mut modbus = ModbusRtu::new(&mut peer, NoopDelay::new(), 19_200);
+        assert_eq!(modbus.read_holding_registers(0x61, 0x0000, &mut registers), Err(Error::TooManyRegisters));
+
+        peer.done();
+    }
+}
@@ -1,12 +1,44 @@
-// TODO: Why does declaring no_std here cause a compiler warning? Is this
-// something which gets declared per crate?
-//
-// #![no_std]
+use core::convert::TryFrom;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::RangeInclusive;
+use embedded_hal_1::delay::DelayNs;
+use embedded_hal_1::i2c::{self, ErrorKind, I2c};
+use defmt::Format;
 
+use crate::co2_sensor::{Co2Sensor, MeasuringCo2Sensor, SensorError};
+use crate::sensirion::{command_frame, command_with_argument_frame, CrcError};
 
-use crc_all::Crc;
-use embedded_hal::blocking::i2c::{Read, Write};
-use defmt::Format;
+
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod modbus;
+pub mod protocol;
+#[cfg(feature = "sim")]
+pub mod sim;
+
+
+use protocol::{
+    CMD_ALTITUDE_COMPENSATION,
+    CMD_AUTOMATIC_SELF_CALIBRATION,
+    CMD_FORCED_RECALIBRATION_VALUE,
+    CMD_GET_DATA_READY,
+    CMD_MEASUREMENT_INTERVAL,
+    CMD_READ_FIRMWARE_VERSION,
+    CMD_READ_MEASUREMENT,
+    CMD_SOFT_RESET,
+    CMD_START_CONTINUOUS_MEASUREMENT,
+    CMD_STOP_CONTINUOUS_MEASUREMENT,
+    CMD_TEMPERATURE_OFFSET,
+    MEASUREMENT_RESPONSE_LEN,
+    WORD_RESPONSE_LEN,
+    check_forced_recalibration_value,
+    decode_data_ready,
+    decode_firmware_version,
+    decode_measurement,
+    decode_word,
+    pressure_argument,
+};
 
 
 
@@ -20,7 +52,20 @@ use defmt::Format;
 // matching?
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Error<E> {
-    CrcError,
+    // The CRC of a word from a response did not match its content.
+    CrcError(CrcError),
+    // An argument outside the range accepted by the sensor got rejected
+    // without talking to it. Carries the command and the rejected argument.
+    InvalidArgument{ command: u16, argument: u16 },
+    // The sensor did not report a measurement as ready in time.
+    NotReady,
+    // Reading back a setting did not return the value just written to the
+    // sensor. Carries the command as well as the written and the read value.
+    ConfigMismatch{ command: u16, written: u16, read: u16 },
+    // A measurement value decoded from the response is not a number or
+    // infinite. Carries the index of the value's first word within the
+    // response.
+    InvalidValue{ word: u8 },
     I2cError(E),
 }
 
@@ -34,16 +79,110 @@ pub struct FirmwareVersion {
 }
 
 
+// Temperature and humidity are absent for sensors which only measure CO2
+// like the SenseAir S8.
 #[derive(Clone, Copy, Debug, Format, PartialEq)]
 pub struct Measurement {
     pub co2_ppm: f32,
-    pub temperature_celsius: f32,
-    pub humidity_percent: f32,
+    pub temperature_celsius: Option<f32>,
+    pub humidity_percent: Option<f32>,
+}
+
+
+// A measurement which passed plausibility validation. It can only be obtained
+// from Measurement::validate.
+#[derive(Clone, Copy, Debug, Format, PartialEq)]
+pub struct ValidMeasurement(Measurement);
+
+
+// Reasons for rejecting a measurement as implausible.
+#[derive(Clone, Copy, Debug, Eq, Format, PartialEq)]
+pub enum Implausibility {
+    // All values are zero. The sensor sends such measurements while warming
+    // up. Sensors which only measure CO2 report zero CO2 then.
+    WarmUp,
+    Co2OutOfRange,
+    TemperatureOutOfRange,
+    HumidityOutOfRange,
+}
+
+
+// The interval between two continuous measurements. The sensor accepts
+// intervals from 2 s up to 1800 s.
+#[derive(Clone, Copy, Debug, Eq, Format, PartialEq)]
+pub struct MeasurementInterval(u16);
+
+
+// The offset the sensor subtracts from its temperature readings to compensate
+// for self-heating, stored in ticks of 0.01 K.
+#[derive(Clone, Copy, Debug, Default, Eq, Format, PartialEq)]
+pub struct TemperatureOffset(u16);
+
+
+// The height above sea level used for compensating CO2 readings when ambient
+// pressure compensation is not in use.
+#[derive(Clone, Copy, Debug, Default, Eq, Format, PartialEq)]
+pub struct Altitude(u16);
+
+
+// The ambient pressure for compensating CO2 readings. The sensor accepts
+// pressures from 700 mbar up to 1400 mbar.
+#[derive(Clone, Copy, Debug, Eq, Format, PartialEq)]
+pub struct AmbientPressure(u16);
+
+
+// Parameters for waiting for a measurement to become ready.
+#[derive(Clone, Copy, Debug, Eq, Format, PartialEq)]
+pub struct WaitOptions {
+    // The pause between two queries of the data-ready status.
+    pub poll_period_ms: u32,
+    // How long to wait for a measurement before giving up.
+    pub timeout_ms: u32,
+    // How many transient errors to tolerate while waiting.
+    pub retries: u8,
+}
+
+
+// How the sensor compensates its CO2 readings for the ambient pressure.
+#[derive(Clone, Copy, Debug, Eq, Format, PartialEq)]
+pub enum Compensation {
+    // The ambient pressure as passed when starting continuous measurement.
+    AmbientPressure(AmbientPressure),
+    // The height above sea level stored in the sensor.
+    Altitude(Altitude),
+}
+
+
+// The settings for operating the sensor. Defaults to the sensor's power-on
+// defaults.
+#[derive(Clone, Copy, Debug, Default, Eq, Format, PartialEq)]
+pub struct Scd30Config {
+    pub measurement_interval: MeasurementInterval,
+    pub compensation: Compensation,
+    pub temperature_offset: TemperatureOffset,
+    pub automatic_self_calibration: bool,
 }
 
 
-pub struct Scd30<I2C: Read + Write> {
+// Marker types for the sensor's measurement mode. Starting and stopping
+// continuous measurement consumes the driver and returns it in the new mode.
+// Commands which only make sense in one mode are only available there.
+pub struct Idle;
+pub struct Measuring;
+
+
+pub struct Scd30<I2C: I2c, D: DelayNs, MODE = Idle> {
     i2c: I2C,
+    delay: D,
+    mode: PhantomData<MODE>,
+}
+
+
+// Returned from a failed mode change. It hands back the driver in its
+// previous mode for retrying.
+pub struct ModeChangeError<E, SENSOR> {
+    pub error: E,
+    pub sensor: SENSOR,
 }
 
 
@@ -51,7 +190,256 @@ pub struct Scd30<I2C: Read + Write> {
 
 pub const I2C_ADDRESS: u8 = 0x61;
 
+// Range of reference values accepted for forced recalibration (FRC).
+pub const FRC_MIN_PPM: u16 = 400;
+pub const FRC_MAX_PPM: u16 = 2000;
+
+
+// The 'Interface Description Sensirion SCD30 Sensor Module' requires a pause
+// of at least 3 ms between the stop of a command and the start of reading its
+// response.
+const COMMAND_RESPONSE_PAUSE_US: u32 = 3_000;
+
+
+
+
+impl MeasurementInterval {
+    pub const MIN_SECONDS: u16 = 2;
+    pub const MAX_SECONDS: u16 = 1800;
+
+
+    pub fn from_seconds(seconds: u16) -> Option<Self> {
+        if (Self::MIN_SECONDS..=Self::MAX_SECONDS).contains(&seconds) {
+            Some(MeasurementInterval(seconds))
+        } else {
+            None
+        }
+    }
+
+
+    pub fn seconds(&self) -> u16 {
+        self.0
+    }
+}
+
 
+impl Measurement {
+    // Physical measurement ranges of the sensor from its datasheet.
+    pub const CO2_RANGE_PPM: RangeInclusive<f32> = 0.0..=40_000.0;
+    pub const TEMPERATURE_RANGE_CELSIUS: RangeInclusive<f32> = -40.0..=70.0;
+    pub const HUMIDITY_RANGE_PERCENT: RangeInclusive<f32> = 0.0..=100.0;
+
+
+    // Checks the values against the sensor's physical ranges and rejects the
+    // all-zero measurements sent during warm-up. Values which are not a
+    // number are out of range as well. Absent values are fine, but do not
+    // count against a warm-up.
+    pub fn validate(&self) -> Result<ValidMeasurement, Implausibility> {
+        let zero_or_absent = |value: Option<f32>| value.is_none() || value == Some(0.0);
+
+        if self.co2_ppm == 0.0 && zero_or_absent(self.temperature_celsius) && zero_or_absent(self.humidity_percent) {
+            Err(Implausibility::WarmUp)
+        } else if !Self::CO2_RANGE_PPM.contains(&self.co2_ppm) {
+            Err(Implausibility::Co2OutOfRange)
+        } else if matches!(self.temperature_celsius, Some(temperature) if !Self::TEMPERATURE_RANGE_CELSIUS.contains(&temperature)) {
+            Err(Implausibility::TemperatureOutOfRange)
+        } else if matches!(self.humidity_percent, Some(humidity) if !Self::HUMIDITY_RANGE_PERCENT.contains(&humidity)) {
+            Err(Implausibility::HumidityOutOfRange)
+        } else {
+            Ok(ValidMeasurement(*self))
+        }
+    }
+}
+
+
+impl ValidMeasurement {
+    pub fn measurement(&self) -> &Measurement {
+        &self.0
+    }
+}
+
+
+impl Default for MeasurementInterval {
+    // The sensor's power-on default.
+    fn default() -> Self {
+        MeasurementInterval(Self::MIN_SECONDS)
+    }
+}
+
+
+impl TryFrom<u16> for MeasurementInterval {
+    type Error = u16;
+
+    fn try_from(seconds: u16) -> Result<Self, Self::Error> {
+        Self::from_seconds(seconds).ok_or(seconds)
+    }
+}
+
+
+impl Default for Compensation {
+    fn default() -> Self {
+        Compensation::Altitude(Altitude::default())
+    }
+}
+
+
+impl Scd30Config {
+    // The pressure to pass when starting continuous measurement. None
+    // disables ambient pressure compensation in favor of the altitude.
+    pub fn pressure(&self) -> Option<AmbientPressure> {
+        match self.compensation {
+            Compensation::AmbientPressure(pressure) => Some(pressure),
+            Compensation::Altitude(_) => None,
+        }
+    }
+}
+
+
+impl Default for WaitOptions {
+    // Suits the sensor's default measurement interval of 2 s.
+    fn default() -> Self {
+        WaitOptions{ poll_period_ms: 100, timeout_ms: 3_000, retries: 3 }
+    }
+}
+
+
+impl TemperatureOffset {
+    pub fn from_centikelvin(centikelvin: u16) -> Self {
+        TemperatureOffset(centikelvin)
+    }
+
+
+    // Returns None for offsets which can't be represented by the sensor
+    // (negative ones or ones larger than 655.35 K).
+    pub fn from_kelvin(kelvin: f32) -> Option<Self> {
+        let centikelvin = kelvin * 100.0;
+
+        if (0.0..=u16::MAX as f32).contains(&centikelvin) {
+            Some(TemperatureOffset((centikelvin + 0.5) as u16))
+        } else {
+            None
+        }
+    }
+
+
+    pub fn centikelvin(&self) -> u16 {
+        self.0
+    }
+
+
+    pub fn kelvin(&self) -> f32 {
+        self.0 as f32 / 100.0
+    }
+}
+
+
+impl AmbientPressure {
+    pub const MIN_MBAR: u16 = 700;
+    pub const MAX_MBAR: u16 = 1400;
+
+
+    pub fn from_mbar(mbar: u16) -> Option<Self> {
+        if (Self::MIN_MBAR..=Self::MAX_MBAR).contains(&mbar) {
+            Some(AmbientPressure(mbar))
+        } else {
+            None
+        }
+    }
+
+
+    // Takes a reading from a barometer and rounds it to full millibars.
+    pub fn from_pascal(pascal: f32) -> Option<Self> {
+        let mbar = pascal / 100.0 + 0.5;
+
+        if (Self::MIN_MBAR as f32..Self::MAX_MBAR as f32 + 1.0).contains(&mbar) {
+            Self::from_mbar(mbar as u16)
+        } else {
+            None
+        }
+    }
+
+
+    pub fn mbar(&self) -> u16 {
+        self.0
+    }
+}
+
+
+impl TryFrom<u16> for AmbientPressure {
+    type Error = u16;
+
+    fn try_from(mbar: u16) -> Result<Self, Self::Error> {
+        Self::from_mbar(mbar).ok_or(mbar)
+    }
+}
+
+
+impl Altitude {
+    pub fn from_meters(meters: u16) -> Self {
+        Altitude(meters)
+    }
+
+
+    pub fn meters(&self) -> u16 {
+        self.0
+    }
+}
+
+
+// Debug-print only the error as the driver does not implement Debug. This
+// still allows unwrapping results from mode changes.
+impl<E: fmt::Debug, SENSOR> fmt::Debug for ModeChangeError<E, SENSOR> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModeChangeError")
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+
+// The I2C implementation's error gets formatted via its Debug implementation
+// as not all of them implement Format.
+impl<E: fmt::Debug> Format for Error<E> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::CrcError(error) => defmt::write!(f, "{}", error),
+            Error::InvalidArgument{ command, argument } =>
+                defmt::write!(f, "invalid argument {=u16} for command {=u16:#06x}", argument, command),
+            Error::NotReady => defmt::write!(f, "sensor not ready"),
+            Error::ConfigMismatch{ command, written, read } =>
+                defmt::write!(f, "command {=u16:#06x} read back {=u16} instead of {=u16}", command, read, written),
+            Error::InvalidValue{ word } => defmt::write!(f, "invalid value in word {=u8}", word),
+            Error::I2cError(error) => defmt::write!(f, "I2C error: {}", defmt::Debug2Format(error)),
+        }
+    }
+}
+
+
+impl<E: Format, SENSOR> Format for ModeChangeError<E, SENSOR> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "mode change failed: {}", self.error)
+    }
+}
+
+
+impl<E: i2c::Error> SensorError for Error<E> {
+    // Whether retrying might succeed. This is the case for responses garbled
+    // on the bus and for the sensor not acknowledging while it is busy. Note
+    // that errors from embedded-hal 0.2 implementations wrapped by
+    // compat::Eh0I2c don't tell whether they were caused by a NACK.
+    fn is_transient(&self) -> bool {
+        match self {
+            Error::CrcError(_) => true,
+            Error::I2cError(error) => matches!(error.kind(), ErrorKind::NoAcknowledge(_)),
+            _ => false,
+        }
+    }
+
+
+    fn not_ready() -> Self {
+        Error::NotReady
+    }
+}
 
 
 // Allow automatic conversion from the I2C implementation's error type to the
@@ -63,140 +451,815 @@ impl<E> From<E> for Error<E> {
 }
 
 
-impl<I2C, E> Scd30<I2C> where I2C: Read<Error = E> + Write<Error = E> {
-    pub fn new(i2c: I2C) -> Self {
-        Scd30{ i2c }
+
+// Starting measurement and changing the sensor's persistent configuration is
+// only possible while it is not measuring. This keeps a running series of
+// measurements consistent.
+impl<I2C, D, E> Scd30<I2C, D, Idle> where I2C: I2c<Error = E>, D: DelayNs {
+    // The driver takes ownership of a delay for pausing between commands and
+    // reading their responses.
+    pub fn new(i2c: I2C, delay: D) -> Self {
+        Scd30{ i2c, delay, mode: PhantomData }
     }
 
 
-    pub fn get_firmware_version(&mut self) -> Result<FirmwareVersion, Error<E>> {
-        let command: [u8; 2] = [0xd1, 0x00];
-        let mut response = [0u8; 3];
+    // Passing an ambient pressure enables pressure compensation. Otherwise,
+    // the sensor compensates for the configured altitude.
+    pub fn start_continuous_measurement(self, pressure: Option<AmbientPressure>) -> Result<Scd30<I2C, D, Measuring>, ModeChangeError<Error<E>, Self>> {
+        let mut sensor = self;
 
-        self.i2c.write(I2C_ADDRESS, &command)?;
-        self.i2c.read(I2C_ADDRESS, &mut response)?;
+        match sensor.write_command_with_argument(CMD_START_CONTINUOUS_MEASUREMENT, pressure_argument(pressure)) {
+            Ok(()) => Ok(sensor.into_mode()),
+            Err(error) => Err(ModeChangeError{ error, sensor }),
+        }
+    }
 
-        let major = response[0];
-        let minor = response[1];
-        let response_crc = response[2];
-        let our_crc = self.sdc30_crc(&response[0..2]);
-        defmt::trace!("response: {=[u8]}, our_crc: {=u8}", response, our_crc);
 
-        if response_crc == our_crc {
-            Ok(FirmwareVersion{ major, minor })
-        } else {
-            Err(Error::<E>::CrcError)
+    pub fn set_measurement_interval(&mut self, interval: MeasurementInterval) -> Result<(), Error<E>> {
+        self.write_command_with_argument(CMD_MEASUREMENT_INTERVAL, interval.seconds())
+    }
+
+
+    // Turns automatic self-calibration (ASC) on or off. ASC needs the sensor
+    // to see fresh air (about 400 ppm) for at least one hour a day. The
+    // setting is kept in the sensor's non-volatile memory.
+    pub fn set_automatic_self_calibration(&mut self, active: bool) -> Result<(), Error<E>> {
+        self.write_command_with_argument(CMD_AUTOMATIC_SELF_CALIBRATION, active as u16)
+    }
+
+
+    // The offset is kept in the sensor's non-volatile memory.
+    pub fn set_temperature_offset(&mut self, offset: TemperatureOffset) -> Result<(), Error<E>> {
+        self.write_command_with_argument(CMD_TEMPERATURE_OFFSET, offset.centikelvin())
+    }
+
+
+    // Altitude compensation is overridden by ambient pressure compensation
+    // when starting continuous measurement with a non-zero pressure. The
+    // altitude is kept in the sensor's non-volatile memory.
+    pub fn set_altitude_compensation(&mut self, altitude: Altitude) -> Result<(), Error<E>> {
+        self.write_command_with_argument(CMD_ALTITUDE_COMPENSATION, altitude.meters())
+    }
+
+
+    // Restarts the sensor as if it had been power cycled. Settings stored in
+    // its non-volatile memory (like the measurement interval) are retained.
+    pub fn soft_reset(&mut self) -> Result<(), Error<E>> {
+        self.write_command(CMD_SOFT_RESET)
+    }
+
+
+    // Applies all settings from the given configuration except for the
+    // ambient pressure which gets passed when starting continuous
+    // measurement. A continuous measurement left running by a previous boot
+    // gets stopped first. Each setting gets read back for verifying that the
+    // sensor accepted it. Settings already in place are not written again
+    // to spare the sensor's non-volatile memory.
+    pub fn configure(&mut self, config: &Scd30Config) -> Result<(), Error<E>> {
+        self.write_command(CMD_STOP_CONTINUOUS_MEASUREMENT)?;
+        self.apply_setting(CMD_MEASUREMENT_INTERVAL, config.measurement_interval.seconds())?;
+        self.apply_setting(CMD_TEMPERATURE_OFFSET, config.temperature_offset.centikelvin())?;
+        self.apply_setting(CMD_AUTOMATIC_SELF_CALIBRATION, config.automatic_self_calibration as u16)?;
+        if let Compensation::Altitude(altitude) = config.compensation {
+            self.apply_setting(CMD_ALTITUDE_COMPENSATION, altitude.meters())?;
         }
+
+        Ok(())
     }
 
 
-    pub fn get_measurement(&mut self) -> Result<Measurement, Error<E>> {
-        let command: [u8; 2] = [0x03, 0x00];
-        let mut response = [0u8; 18];
-
-        self.i2c.write(I2C_ADDRESS, &command)?;
-        // FIXME: The 'Interface Description Sensirion SCD30 Sensor Module'
-        // points out that there should be a pause of at least 3 ms between the
-        // stop of the command and the start for reading the response.
-        // Shouldn't there be an explicit delay here?
-        self.i2c.read(I2C_ADDRESS, &mut response)?;
-        defmt::trace!("response: {=[u8]}", response);
+    fn apply_setting(&mut self, command: u16, value: u16) -> Result<(), Error<E>> {
+        if self.read_word(command)? == value {
+            return Ok(());
+        }
 
-        let co2_m_be = &response[0..2];
-        let co2_m_crc = response[2];
-        let co2_l_be = &response[3..5];
-        let co2_l_crc = response[5];
-        let t_m_be = &response[6..8];
-        let t_m_crc = response[8];
-        let t_l_be = &response[9..11];
-        let t_l_crc = response[11];
-        let rh_m_be = &response[12..14];
-        let rh_m_crc = response[14];
-        let rh_l_be = &response[15..17];
-        let rh_l_crc = response[17];
-
-        if co2_m_crc == self.sdc30_crc(&co2_m_be)
-            && co2_l_crc == self.sdc30_crc(&co2_l_be)
-            && t_m_crc == self.sdc30_crc(&t_m_be)
-            && t_l_crc == self.sdc30_crc(&t_l_be)
-            && rh_m_crc == self.sdc30_crc(&rh_m_be)
-            && rh_l_crc == self.sdc30_crc(&rh_l_be)
-        {
-            let measurement = Measurement {
-                co2_ppm: f32::from_bits(u32::from_be_bytes([
-                    co2_m_be[0],
-                    co2_m_be[1],
-                    co2_l_be[0],
-                    co2_l_be[1],
-                ])),
-                temperature_celsius: f32::from_bits(u32::from_be_bytes([
-                    t_m_be[0],
-                    t_m_be[1],
-                    t_l_be[0],
-                    t_l_be[1],
-                ])),
-                humidity_percent: f32::from_bits(u32::from_be_bytes([
-                    rh_m_be[0],
-                    rh_m_be[1],
-                    rh_l_be[0],
-                    rh_l_be[1],
-                ])),
-            };
-
-            Ok(measurement)
+        self.write_command_with_argument(command, value)?;
+        let read = self.read_word(command)?;
+        if read == value {
+            Ok(())
         } else {
-            Err(Error::<E>::CrcError)
+            Err(Error::ConfigMismatch{ command, written: value, read })
         }
     }
+}
+
+
+// Reading measurements and forced recalibration require the sensor to
+// measure continuously.
+impl<I2C, D, E> Scd30<I2C, D, Measuring> where I2C: I2c<Error = E>, D: DelayNs {
+    pub fn get_measurement(&mut self) -> Result<Measurement, Error<E>> {
+        let mut response = [0u8; MEASUREMENT_RESPONSE_LEN];
+
+        self.read_response(CMD_READ_MEASUREMENT, &mut response)?;
+        decode_measurement(&response)
+    }
 
 
     pub fn is_measurement_ready(&mut self) -> Result<bool, Error<E>> {
-        let command: [u8; 2] = [0x02, 0x02];
-        let mut response = [0u8; 3];
-
-        self.i2c.write(I2C_ADDRESS, &command)?;
-        self.i2c.read(I2C_ADDRESS, &mut response)?;
-
-        // TODO: It seems there is no such thing as a slice with compile-time
-        // constant length to please u16::from_be_bytes. Is there any way of
-        // getting a slice into a suitable form for from_be_bytes?
-        let mut ready_be = [0u8; 2];
-        ready_be.copy_from_slice(&response[0..2]);
-        let response_crc = response[2];
-        let our_crc = self.sdc30_crc(&ready_be);
-
-        if response_crc == our_crc {
-            let ready = u16::from_be_bytes(ready_be);
-            Ok(ready == 1u16)
-        } else {
-            Err(Error::<E>::CrcError)
+        let mut response = [0u8; WORD_RESPONSE_LEN];
+
+        self.read_response(CMD_GET_DATA_READY, &mut response)?;
+        decode_data_ready(&response)
+    }
+
+
+
+    // Updates the ambient pressure used for compensation without
+    // interrupting continuous measurement. This allows feeding in readings
+    // from a barometer periodically.
+    pub fn set_ambient_pressure(&mut self, pressure: Option<AmbientPressure>) -> Result<(), Error<E>> {
+        self.write_command_with_argument(CMD_START_CONTINUOUS_MEASUREMENT, pressure_argument(pressure))
+    }
+
+
+    // Recalibrates the sensor against a known CO2 concentration. The sensor
+    // should have been measuring continuously in this environment for at
+    // least two minutes before. Values outside the range accepted by the
+    // sensor get rejected without talking to it.
+    pub fn set_forced_recalibration_value(&mut self, co2_ppm: u16) -> Result<(), Error<E>> {
+        check_forced_recalibration_value(co2_ppm)?;
+        self.write_command_with_argument(CMD_FORCED_RECALIBRATION_VALUE, co2_ppm)
+    }
+
+
+    pub fn stop_continuous_measurement(self) -> Result<Scd30<I2C, D, Idle>, ModeChangeError<Error<E>, Self>> {
+        let mut sensor = self;
+
+        match sensor.write_command(CMD_STOP_CONTINUOUS_MEASUREMENT) {
+            Ok(()) => Ok(sensor.into_mode()),
+            Err(error) => Err(ModeChangeError{ error, sensor }),
         }
     }
+}
 
 
-    fn new_sdc30_crc(&self) -> Crc<u8> {
-        // See 'Interface Description Sensirion SCD30 Sensor Module', section
-        // 1.1.3 'I2C Checksum calculation' for CRC parameter definition.
-        Crc::<u8>::new(0x31, 8, 0xff, 0x00, false)
+// Querying the sensor's state and configuration is possible in any mode.
+impl<I2C, D, E, MODE> Scd30<I2C, D, MODE> where I2C: I2c<Error = E>, D: DelayNs {
+    // Gives back the I2C bus and the delay.
+    pub fn release(self) -> (I2C, D) {
+        (self.i2c, self.delay)
     }
 
 
-    fn sdc30_crc(&self, data: &[u8]) -> u8 {
-        let mut crc = self.new_sdc30_crc();
-        crc.update(data);
-        crc.finish()
+    pub fn get_firmware_version(&mut self) -> Result<FirmwareVersion, Error<E>> {
+        let mut response = [0u8; WORD_RESPONSE_LEN];
+
+        self.read_response(CMD_READ_FIRMWARE_VERSION, &mut response)?;
+        decode_firmware_version(&response)
     }
 
 
-    pub fn start_continuous_measurement(&mut self, pressure: u16) -> Result<(), Error<E>> {
-        let mut command: [u8; 5] = [0x00, 0x10, 0x00, 0x00, 0x00];
+    pub fn get_measurement_interval(&mut self) -> Result<MeasurementInterval, Error<E>> {
+        let seconds = self.read_word(CMD_MEASUREMENT_INTERVAL)?;
+        // The sensor only stores intervals it accepted before, so there is
+        // no need for bothering the caller with a value out of range here.
+        Ok(MeasurementInterval(seconds))
+    }
 
-        let pressure_be = pressure.to_be_bytes();
-        command[2..4].copy_from_slice(&pressure_be);
-        command[4] = self.sdc30_crc(&pressure_be);
-        defmt::trace!("command: {=[u8]}", command);
 
-        self.i2c.write(I2C_ADDRESS, &command)?;
+    // Returns whether automatic self-calibration (ASC) is active.
+    pub fn get_automatic_self_calibration(&mut self) -> Result<bool, Error<E>> {
+        let active = self.read_word(CMD_AUTOMATIC_SELF_CALIBRATION)?;
+        Ok(active == 1u16)
+    }
+
+
+    // Returns the reference value used by the last forced recalibration (FRC)
+    // or 400 ppm if the sensor has never been recalibrated this way.
+    pub fn get_forced_recalibration_value(&mut self) -> Result<u16, Error<E>> {
+        self.read_word(CMD_FORCED_RECALIBRATION_VALUE)
+    }
+
+
+    pub fn get_temperature_offset(&mut self) -> Result<TemperatureOffset, Error<E>> {
+        let centikelvin = self.read_word(CMD_TEMPERATURE_OFFSET)?;
+        Ok(TemperatureOffset(centikelvin))
+    }
+
+
+    pub fn get_altitude_compensation(&mut self) -> Result<Altitude, Error<E>> {
+        let meters = self.read_word(CMD_ALTITUDE_COMPENSATION)?;
+        Ok(Altitude(meters))
+    }
+
+
+    fn into_mode<NEW>(self) -> Scd30<I2C, D, NEW> {
+        Scd30{ i2c: self.i2c, delay: self.delay, mode: PhantomData }
+    }
+
+
+    fn write_command(&mut self, command: u16) -> Result<(), Error<E>> {
+        let frame = command_frame(command);
+        defmt::trace!("command: {=[u8]}", frame);
+
+        self.i2c.write(I2C_ADDRESS, &frame)?;
+        Ok(())
+    }
+
+
+    fn write_command_with_argument(&mut self, command: u16, argument: u16) -> Result<(), Error<E>> {
+        let frame = command_with_argument_frame(command, argument);
+        defmt::trace!("command: {=[u8]}", frame);
+
+        self.i2c.write(I2C_ADDRESS, &frame)?;
         Ok(())
     }
+
+
+    // Issues a command and reads its response after the pause required by
+    // the sensor. This can't be expressed as a single I2C transaction as the
+    // sensor expects a stop condition after the command.
+    fn read_response(&mut self, command: u16, response: &mut [u8]) -> Result<(), Error<E>> {
+        self.write_command(command)?;
+        self.delay.delay_us(COMMAND_RESPONSE_PAUSE_US);
+        self.i2c.read(I2C_ADDRESS, response)?;
+        defmt::trace!("response: {=[u8]}", response);
+
+        Ok(())
+    }
+
+
+    // Issues a command which gets answered with a single word and returns
+    // this word after checking its CRC.
+    fn read_word(&mut self, command: u16) -> Result<u16, Error<E>> {
+        let mut response = [0u8; WORD_RESPONSE_LEN];
+
+        self.read_response(command, &mut response)?;
+        decode_word(&response)
+    }
+}
+
+
+impl<I2C, D, E> Co2Sensor for Scd30<I2C, D, Idle> where I2C: I2c<Error = E>, D: DelayNs, E: i2c::Error {
+    type Error = Error<E>;
+    type Measuring = Scd30<I2C, D, Measuring>;
+
+    fn start_measurement(self, pressure: Option<AmbientPressure>) -> Result<Self::Measuring, ModeChangeError<Self::Error, Self>> {
+        self.start_continuous_measurement(pressure)
+    }
+}
+
+
+impl<I2C, D, E> MeasuringCo2Sensor for Scd30<I2C, D, Measuring> where I2C: I2c<Error = E>, D: DelayNs, E: i2c::Error {
+    type Error = Error<E>;
+
+    fn is_measurement_ready(&mut self) -> Result<bool, Self::Error> {
+        Scd30::is_measurement_ready(self)
+    }
+
+
+    fn read_measurement(&mut self) -> Result<Measurement, Self::Error> {
+        self.get_measurement()
+    }
+
+
+    fn pause_ms(&mut self, ms: u32) {
+        self.delay.delay_ms(ms);
+    }
+
+
+    fn set_ambient_pressure(&mut self, pressure: AmbientPressure) -> Result<(), Self::Error> {
+        Scd30::set_ambient_pressure(self, Some(pressure))
+    }
+
+
+    fn set_forced_recalibration_value(&mut self, co2_ppm: u16) -> Result<(), Self::Error> {
+        Scd30::set_forced_recalibration_value(self, co2_ppm)
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RecordingDelay;
+    use embedded_hal_1::i2c::NoAcknowledgeSource;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+    use std::cell::Cell;
+
+
+    // Creates a driver for a sensor which is already measuring.
+    fn measuring<D: DelayNs>(i2c: Mock, delay: D) -> Scd30<Mock, D, Measuring> {
+        Scd30{ i2c, delay, mode: PhantomData }
+    }
+
+
+    #[test]
+    fn start_continuous_measurement_sends_pressure_with_crc() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x00, 0x10, 0x03, 0xfc, 0x53]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        sensor.start_continuous_measurement(AmbientPressure::from_mbar(1020)).unwrap();
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn get_measurement_pauses_before_reading_response() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x03, 0x00]),
+            Transaction::read(I2C_ADDRESS, vec![
+                0x43, 0xdb, 0xcb, 0x8c, 0x2e, 0x8f,
+                0x41, 0xd9, 0x70, 0xe7, 0xff, 0xf5,
+                0x42, 0x43, 0xbf, 0x3a, 0x1b, 0x74,
+            ]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let paused_ns = Cell::new(0);
+        let mut sensor = measuring(i2c.clone(), RecordingDelay(&paused_ns));
+
+        // Example from the interface description.
+        let measurement = sensor.get_measurement().unwrap();
+        assert_eq!(measurement.co2_ppm, 439.09515);
+        assert_eq!(measurement.temperature_celsius, Some(27.23828));
+        assert_eq!(measurement.humidity_percent, Some(48.806744));
+        assert!(paused_ns.get() >= 3_000_000);
+
+        i2c.done();
+    }
+
+
+    // Example response from section 1.5 'Read measurement'.
+    fn measurement_transactions() -> [Transaction; 2] {
+        [
+            Transaction::write(I2C_ADDRESS, vec![0x03, 0x00]),
+            Transaction::read(I2C_ADDRESS, vec![
+                0x43, 0xdb, 0xcb, 0x8c, 0x2e, 0x8f,
+                0x41, 0xd9, 0x70, 0xe7, 0xff, 0xf5,
+                0x42, 0x43, 0xbf, 0x3a, 0x1b, 0x74,
+            ]),
+        ]
+    }
+
+
+    fn data_ready_transactions(ready: bool) -> [Transaction; 2] {
+        let response = if ready { vec![0x00, 0x01, 0xb0] } else { vec![0x00, 0x00, 0x81] };
+
+        [
+            Transaction::write(I2C_ADDRESS, vec![0x02, 0x02]),
+            Transaction::read(I2C_ADDRESS, response),
+        ]
+    }
+
+
+    #[test]
+    fn wait_for_measurement_polls_until_ready() {
+        let mut expectations = Vec::new();
+        expectations.extend(data_ready_transactions(false));
+        expectations.extend(data_ready_transactions(false));
+        expectations.extend(data_ready_transactions(true));
+        expectations.extend(measurement_transactions());
+        let mut i2c = Mock::new(&expectations);
+        let paused_ns = Cell::new(0);
+        let mut sensor = measuring(i2c.clone(), RecordingDelay(&paused_ns));
+
+        let options = WaitOptions{ poll_period_ms: 500, timeout_ms: 2_000, retries: 0 };
+        let measurement = sensor.wait_for_measurement(&options).unwrap();
+        assert_eq!(measurement.co2_ppm, 439.09515);
+        // Two poll periods plus the pauses before reading each response.
+        assert_eq!(paused_ns.get(), 2 * 500_000_000 + 4 * 3_000_000);
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn wait_for_measurement_retries_transient_errors() {
+        let mut expectations = vec![
+            Transaction::write(I2C_ADDRESS, vec![0x02, 0x02])
+                .with_error(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)),
+            Transaction::write(I2C_ADDRESS, vec![0x02, 0x02]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x01, 0xb1]),
+        ];
+        expectations.extend(data_ready_transactions(true));
+        expectations.extend(measurement_transactions());
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = measuring(i2c.clone(), NoopDelay::new());
+
+        let options = WaitOptions{ retries: 2, ..WaitOptions::default() };
+        assert!(sensor.wait_for_measurement(&options).is_ok());
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn wait_for_measurement_gives_up() {
+        // Too many transient errors.
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x02, 0x02]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x01, 0xb1]),
+            Transaction::write(I2C_ADDRESS, vec![0x02, 0x02]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x01, 0xb1]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = measuring(i2c.clone(), NoopDelay::new());
+
+        let options = WaitOptions{ retries: 1, ..WaitOptions::default() };
+        assert_eq!(sensor.wait_for_measurement(&options), Err(Error::CrcError(CrcError{ word: 0, received: 0xb1, expected: 0xb0 })));
+        i2c.done();
+
+        // Measurement does not become ready in time.
+        let mut expectations = Vec::new();
+        for _ in 0..3 {
+            expectations.extend(data_ready_transactions(false));
+        }
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = measuring(i2c.clone(), NoopDelay::new());
+
+        let options = WaitOptions{ poll_period_ms: 500, timeout_ms: 1_000, retries: 0 };
+        assert_eq!(sensor.wait_for_measurement(&options), Err(Error::NotReady));
+        i2c.done();
+    }
+
+
+    #[test]
+    fn set_ambient_pressure_while_measuring() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x00, 0x10, 0x03, 0xfc, 0x53]),
+            Transaction::write(I2C_ADDRESS, vec![0x00, 0x10, 0x00, 0x00, 0x81]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = measuring(i2c.clone(), NoopDelay::new());
+
+        sensor.set_ambient_pressure(AmbientPressure::from_pascal(101_970.0)).unwrap();
+        sensor.set_ambient_pressure(None).unwrap();
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn ambient_pressure_range() {
+        assert_eq!(AmbientPressure::from_mbar(699), None);
+        assert_eq!(AmbientPressure::from_mbar(700).map(|p| p.mbar()), Some(700));
+        assert_eq!(AmbientPressure::from_mbar(1400).map(|p| p.mbar()), Some(1400));
+        assert_eq!(AmbientPressure::from_mbar(1401), None);
+        assert_eq!(AmbientPressure::try_from(0), Err(0));
+
+        assert_eq!(AmbientPressure::from_pascal(101_325.0).map(|p| p.mbar()), Some(1013));
+        assert_eq!(AmbientPressure::from_pascal(69_950.0).map(|p| p.mbar()), Some(700));
+        assert_eq!(AmbientPressure::from_pascal(69_940.0), None);
+        assert_eq!(AmbientPressure::from_pascal(140_049.0).map(|p| p.mbar()), Some(1400));
+        assert_eq!(AmbientPressure::from_pascal(140_050.0), None);
+        assert_eq!(AmbientPressure::from_pascal(f32::NAN), None);
+    }
+
+
+    #[test]
+    fn stop_continuous_measurement() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x01, 0x04]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let sensor = measuring(i2c.clone(), NoopDelay::new());
+
+        sensor.stop_continuous_measurement().unwrap();
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn failed_mode_change_returns_driver() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x00, 0x10, 0x00, 0x00, 0x81])
+                .with_error(ErrorKind::Other),
+            Transaction::write(I2C_ADDRESS, vec![0x00, 0x10, 0x00, 0x00, 0x81]),
+            Transaction::write(I2C_ADDRESS, vec![0x01, 0x04]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        let failure = match sensor.start_continuous_measurement(None) {
+            Ok(_) => panic!("starting measurement unexpectedly succeeded"),
+            Err(failure) => failure,
+        };
+        assert_eq!(failure.error, Error::I2cError(ErrorKind::Other));
+        let sensor = failure.sensor.start_continuous_measurement(None).unwrap();
+        sensor.stop_continuous_measurement().unwrap();
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn set_measurement_interval() {
+        // Example from the interface description.
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x46, 0x00, 0x00, 0x02, 0xe3]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        let interval = MeasurementInterval::from_seconds(2).unwrap();
+        sensor.set_measurement_interval(interval).unwrap();
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn get_measurement_interval() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x46, 0x00]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x05, 0x74]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        let interval = sensor.get_measurement_interval().unwrap();
+        assert_eq!(interval.seconds(), 5);
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn get_measurement_interval_detects_crc_error() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x46, 0x00]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x05, 0x75]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.get_measurement_interval(), Err(Error::CrcError(CrcError{ word: 0, received: 0x75, expected: 0x74 })));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn validate_measurement() {
+        let measurement = Measurement{ co2_ppm: 439.1, temperature_celsius: Some(27.2), humidity_percent: Some(48.8) };
+        assert_eq!(measurement.validate().map(|valid| *valid.measurement()), Ok(measurement));
+
+        let zero = Measurement{ co2_ppm: 0.0, temperature_celsius: Some(0.0), humidity_percent: Some(0.0) };
+        assert_eq!(zero.validate(), Err(Implausibility::WarmUp));
+        assert_eq!(Measurement{ co2_ppm: 40_001.0, ..measurement }.validate(), Err(Implausibility::Co2OutOfRange));
+        assert_eq!(Measurement{ co2_ppm: f32::NAN, ..measurement }.validate(), Err(Implausibility::Co2OutOfRange));
+        assert_eq!(Measurement{ temperature_celsius: Some(-40.5), ..measurement }.validate(), Err(Implausibility::TemperatureOutOfRange));
+        assert_eq!(Measurement{ humidity_percent: Some(100.1), ..measurement }.validate(), Err(Implausibility::HumidityOutOfRange));
+
+        // Zero CO2 alone is within the sensor's range.
+        assert!(Measurement{ co2_ppm: 0.0, ..measurement }.validate().is_ok());
+
+        // A CO2-only sensor reports zero CO2 while warming up.
+        let co2_only = Measurement{ co2_ppm: 0.0, temperature_celsius: None, humidity_percent: None };
+        assert_eq!(co2_only.validate(), Err(Implausibility::WarmUp));
+        assert_eq!(Measurement{ humidity_percent: Some(0.0), ..co2_only }.validate(), Err(Implausibility::WarmUp));
+        assert!(Measurement{ co2_ppm: 412.0, ..co2_only }.validate().is_ok());
+    }
+
+
+    #[test]
+    fn configure_writes_and_verifies_changed_settings() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x01, 0x04]),
+            // The measurement interval is already in place.
+            Transaction::write(I2C_ADDRESS, vec![0x46, 0x00]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x02, 0xe3]),
+            Transaction::write(I2C_ADDRESS, vec![0x54, 0x03]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x00, 0x81]),
+            Transaction::write(I2C_ADDRESS, vec![0x54, 0x03, 0x00, 0x96, 0x1e]),
+            Transaction::write(I2C_ADDRESS, vec![0x54, 0x03]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x96, 0x1e]),
+            Transaction::write(I2C_ADDRESS, vec![0x53, 0x06]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x00, 0x81]),
+            Transaction::write(I2C_ADDRESS, vec![0x53, 0x06, 0x00, 0x01, 0xb0]),
+            Transaction::write(I2C_ADDRESS, vec![0x53, 0x06]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x01, 0xb0]),
+            Transaction::write(I2C_ADDRESS, vec![0x51, 0x02]),
+            Transaction::read(I2C_ADDRESS, vec![0x02, 0x08, 0xe1]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        let config = Scd30Config {
+            compensation: Compensation::Altitude(Altitude::from_meters(520)),
+            temperature_offset: TemperatureOffset::from_centikelvin(150),
+            automatic_self_calibration: true,
+            ..Scd30Config::default()
+        };
+        sensor.configure(&config).unwrap();
+        assert_eq!(config.pressure(), None);
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn configure_stops_measurement_left_running() {
+        // The sensor is still measuring from before a reset of the
+        // microcontroller. It has to stop before any setting gets touched.
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x01, 0x04]),
+            Transaction::write(I2C_ADDRESS, vec![0x46, 0x00]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x02, 0xe3]),
+            Transaction::write(I2C_ADDRESS, vec![0x54, 0x03]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x00, 0x81]),
+            Transaction::write(I2C_ADDRESS, vec![0x53, 0x06]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x00, 0x81]),
+            Transaction::write(I2C_ADDRESS, vec![0x00, 0x10, 0x00, 0x00, 0x81]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        let config = Scd30Config {
+            compensation: Compensation::AmbientPressure(AmbientPressure::from_mbar(1020).unwrap()),
+            ..Scd30Config::default()
+        };
+        sensor.configure(&config).unwrap();
+        sensor.start_continuous_measurement(None).unwrap();
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn configure_reports_mismatch() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x01, 0x04]),
+            Transaction::write(I2C_ADDRESS, vec![0x46, 0x00]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x02, 0xe3]),
+            Transaction::write(I2C_ADDRESS, vec![0x54, 0x03]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x00, 0x81]),
+            Transaction::write(I2C_ADDRESS, vec![0x54, 0x03, 0x00, 0x96, 0x1e]),
+            Transaction::write(I2C_ADDRESS, vec![0x54, 0x03]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x95, 0x4d]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        let config = Scd30Config {
+            compensation: Compensation::AmbientPressure(AmbientPressure::from_mbar(1020).unwrap()),
+            temperature_offset: TemperatureOffset::from_centikelvin(150),
+            ..Scd30Config::default()
+        };
+        assert_eq!(sensor.configure(&config),
+            Err(Error::ConfigMismatch{ command: CMD_TEMPERATURE_OFFSET, written: 150, read: 149 }));
+        assert_eq!(config.pressure().map(|pressure| pressure.mbar()), Some(1020));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn measurement_interval_range() {
+        assert_eq!(MeasurementInterval::from_seconds(1), None);
+        assert_eq!(MeasurementInterval::from_seconds(2).map(|i| i.seconds()), Some(2));
+        assert_eq!(MeasurementInterval::from_seconds(1800).map(|i| i.seconds()), Some(1800));
+        assert_eq!(MeasurementInterval::from_seconds(1801), None);
+        assert_eq!(MeasurementInterval::try_from(0), Err(0));
+    }
+
+
+    #[test]
+    fn set_automatic_self_calibration() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x53, 0x06, 0x00, 0x01, 0xb0]),
+            Transaction::write(I2C_ADDRESS, vec![0x53, 0x06, 0x00, 0x00, 0x81]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        sensor.set_automatic_self_calibration(true).unwrap();
+        sensor.set_automatic_self_calibration(false).unwrap();
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn get_automatic_self_calibration() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x53, 0x06]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x01, 0xb0]),
+            Transaction::write(I2C_ADDRESS, vec![0x53, 0x06]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x00, 0x81]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.get_automatic_self_calibration(), Ok(true));
+        assert_eq!(sensor.get_automatic_self_calibration(), Ok(false));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn set_forced_recalibration_value() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x52, 0x04, 0x01, 0x90, 0x4c]),
+            Transaction::write(I2C_ADDRESS, vec![0x52, 0x04, 0x07, 0xd0, 0x2b]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = measuring(i2c.clone(), NoopDelay::new());
+
+        sensor.set_forced_recalibration_value(400).unwrap();
+        sensor.set_forced_recalibration_value(2000).unwrap();
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn set_forced_recalibration_value_rejects_out_of_range_values() {
+        // Nothing is expected to go out on the bus.
+        let mut i2c = Mock::new(&[]);
+        let mut sensor = measuring(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.set_forced_recalibration_value(0), Err(Error::InvalidArgument{ command: CMD_FORCED_RECALIBRATION_VALUE, argument: 0 }));
+        assert_eq!(sensor.set_forced_recalibration_value(399), Err(Error::InvalidArgument{ command: CMD_FORCED_RECALIBRATION_VALUE, argument: 399 }));
+        assert_eq!(sensor.set_forced_recalibration_value(2001), Err(Error::InvalidArgument{ command: CMD_FORCED_RECALIBRATION_VALUE, argument: 2001 }));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn get_forced_recalibration_value() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x52, 0x04]),
+            Transaction::read(I2C_ADDRESS, vec![0x01, 0xc2, 0x50]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.get_forced_recalibration_value(), Ok(450));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn temperature_offset_round_trip() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x54, 0x03, 0x00, 0xfa, 0xd8]),
+            Transaction::write(I2C_ADDRESS, vec![0x54, 0x03]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0xfa, 0xd8]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        let offset = TemperatureOffset::from_kelvin(2.5).unwrap();
+        sensor.set_temperature_offset(offset).unwrap();
+        assert_eq!(sensor.get_temperature_offset(), Ok(offset));
+        assert_eq!(offset.centikelvin(), 250);
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn temperature_offset_conversion() {
+        assert_eq!(TemperatureOffset::from_kelvin(0.0), Some(TemperatureOffset::from_centikelvin(0)));
+        assert_eq!(TemperatureOffset::from_kelvin(1.234), Some(TemperatureOffset::from_centikelvin(123)));
+        assert_eq!(TemperatureOffset::from_kelvin(655.35), Some(TemperatureOffset::from_centikelvin(65535)));
+        assert_eq!(TemperatureOffset::from_kelvin(-0.5), None);
+        assert_eq!(TemperatureOffset::from_kelvin(655.36), None);
+        assert_eq!(TemperatureOffset::from_kelvin(f32::NAN), None);
+        assert_eq!(TemperatureOffset::from_centikelvin(150).kelvin(), 1.5);
+    }
+
+
+    #[test]
+    fn altitude_compensation_round_trip() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x51, 0x02, 0x06, 0x40, 0xe6]),
+            Transaction::write(I2C_ADDRESS, vec![0x51, 0x02]),
+            Transaction::read(I2C_ADDRESS, vec![0x06, 0x40, 0xe6]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        let altitude = Altitude::from_meters(1600);
+        sensor.set_altitude_compensation(altitude).unwrap();
+        assert_eq!(sensor.get_altitude_compensation(), Ok(altitude));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn soft_reset() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0xd3, 0x04]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        sensor.soft_reset().unwrap();
+
+        i2c.done();
+    }
 }
@@ -0,0 +1,393 @@
+// An async variant of the SCD30 driver for use with an async executor. It
+// mirrors the blocking one from the parent module and shares command framing
+// and response decoding with it via crate::sensirion and the protocol module.
+
+
+use core::marker::PhantomData;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c::{self, I2c};
+
+use crate::co2_sensor::Wait;
+use crate::sensirion::{command_frame, command_with_argument_frame};
+
+use super::{
+    Altitude,
+    AmbientPressure,
+    Compensation,
+    Error,
+    FirmwareVersion,
+    Idle,
+    Measurement,
+    MeasurementInterval,
+    Measuring,
+    ModeChangeError,
+    Scd30Config,
+    TemperatureOffset,
+    WaitOptions,
+    I2C_ADDRESS,
+    COMMAND_RESPONSE_PAUSE_US,
+};
+use super::protocol::{
+    CMD_ALTITUDE_COMPENSATION,
+    CMD_AUTOMATIC_SELF_CALIBRATION,
+    CMD_FORCED_RECALIBRATION_VALUE,
+    CMD_GET_DATA_READY,
+    CMD_MEASUREMENT_INTERVAL,
+    CMD_READ_FIRMWARE_VERSION,
+    CMD_READ_MEASUREMENT,
+    CMD_SOFT_RESET,
+    CMD_START_CONTINUOUS_MEASUREMENT,
+    CMD_STOP_CONTINUOUS_MEASUREMENT,
+    CMD_TEMPERATURE_OFFSET,
+    MEASUREMENT_RESPONSE_LEN,
+    WORD_RESPONSE_LEN,
+    check_forced_recalibration_value,
+    decode_data_ready,
+    decode_firmware_version,
+    decode_measurement,
+    decode_word,
+    pressure_argument,
+};
+
+
+
+
+pub struct Scd30<I2C: I2c, D: DelayNs, MODE = Idle> {
+    i2c: I2C,
+    delay: D,
+    mode: PhantomData<MODE>,
+}
+
+
+
+
+// Starting measurement and changing the sensor's persistent configuration is
+// only possible while it is not measuring. This keeps a running series of
+// measurements consistent.
+impl<I2C, D, E> Scd30<I2C, D, Idle> where I2C: I2c<Error = E>, D: DelayNs {
+    // The driver takes ownership of a delay for pausing between commands and
+    // reading their responses.
+    pub fn new(i2c: I2C, delay: D) -> Self {
+        Scd30{ i2c, delay, mode: PhantomData }
+    }
+
+
+    // Passing an ambient pressure enables pressure compensation. Otherwise,
+    // the sensor compensates for the configured altitude.
+    pub async fn start_continuous_measurement(self, pressure: Option<AmbientPressure>) -> Result<Scd30<I2C, D, Measuring>, ModeChangeError<Error<E>, Self>> {
+        let mut sensor = self;
+
+        match sensor.write_command_with_argument(CMD_START_CONTINUOUS_MEASUREMENT, pressure_argument(pressure)).await {
+            Ok(()) => Ok(sensor.into_mode()),
+            Err(error) => Err(ModeChangeError{ error, sensor }),
+        }
+    }
+
+
+    pub async fn set_measurement_interval(&mut self, interval: MeasurementInterval) -> Result<(), Error<E>> {
+        self.write_command_with_argument(CMD_MEASUREMENT_INTERVAL, interval.seconds()).await
+    }
+
+
+    // Turns automatic self-calibration (ASC) on or off.
+    pub async fn set_automatic_self_calibration(&mut self, active: bool) -> Result<(), Error<E>> {
+        self.write_command_with_argument(CMD_AUTOMATIC_SELF_CALIBRATION, active as u16).await
+    }
+
+
+    pub async fn set_temperature_offset(&mut self, offset: TemperatureOffset) -> Result<(), Error<E>> {
+        self.write_command_with_argument(CMD_TEMPERATURE_OFFSET, offset.centikelvin()).await
+    }
+
+
+    pub async fn set_altitude_compensation(&mut self, altitude: Altitude) -> Result<(), Error<E>> {
+        self.write_command_with_argument(CMD_ALTITUDE_COMPENSATION, altitude.meters()).await
+    }
+
+
+    pub async fn soft_reset(&mut self) -> Result<(), Error<E>> {
+        self.write_command(CMD_SOFT_RESET).await
+    }
+
+
+    // Applies all settings from the given configuration except for the
+    // ambient pressure which gets passed when starting continuous
+    // measurement. A continuous measurement left running by a previous boot
+    // gets stopped first. Each setting gets read back for verifying that the
+    // sensor accepted it. Settings already in place are not written again.
+    pub async fn configure(&mut self, config: &Scd30Config) -> Result<(), Error<E>> {
+        self.write_command(CMD_STOP_CONTINUOUS_MEASUREMENT).await?;
+        self.apply_setting(CMD_MEASUREMENT_INTERVAL, config.measurement_interval.seconds()).await?;
+        self.apply_setting(CMD_TEMPERATURE_OFFSET, config.temperature_offset.centikelvin()).await?;
+        self.apply_setting(CMD_AUTOMATIC_SELF_CALIBRATION, config.automatic_self_calibration as u16).await?;
+        if let Compensation::Altitude(altitude) = config.compensation {
+            self.apply_setting(CMD_ALTITUDE_COMPENSATION, altitude.meters()).await?;
+        }
+
+        Ok(())
+    }
+
+
+    async fn apply_setting(&mut self, command: u16, value: u16) -> Result<(), Error<E>> {
+        if self.read_word(command).await? == value {
+            return Ok(());
+        }
+
+        self.write_command_with_argument(command, value).await?;
+        let read = self.read_word(command).await?;
+        if read == value {
+            Ok(())
+        } else {
+            Err(Error::ConfigMismatch{ command, written: value, read })
+        }
+    }
+}
+
+
+// Reading measurements and forced recalibration require the sensor to
+// measure continuously.
+impl<I2C, D, E> Scd30<I2C, D, Measuring> where I2C: I2c<Error = E>, D: DelayNs {
+    pub async fn get_measurement(&mut self) -> Result<Measurement, Error<E>> {
+        let mut response = [0u8; MEASUREMENT_RESPONSE_LEN];
+
+        self.read_response(CMD_READ_MEASUREMENT, &mut response).await?;
+        decode_measurement(&response)
+    }
+
+
+    pub async fn is_measurement_ready(&mut self) -> Result<bool, Error<E>> {
+        let mut response = [0u8; WORD_RESPONSE_LEN];
+
+        self.read_response(CMD_GET_DATA_READY, &mut response).await?;
+        decode_data_ready(&response)
+    }
+
+
+    // Polls for a new measurement and reads it as soon as it is ready. This
+    // mirrors MeasuringCo2Sensor::wait_for_measurement.
+    pub async fn wait_for_measurement(&mut self, options: &WaitOptions) -> Result<Measurement, Error<E>> where E: i2c::Error {
+        let mut wait = Wait::new(options);
+
+        loop {
+            let result = match self.is_measurement_ready().await {
+                Ok(true) => self.get_measurement().await.map(Some),
+                Ok(false) => Ok(None),
+                Err(error) => Err(error),
+            };
+
+            if let Some(result) = wait.evaluate(result) {
+                return result;
+            }
+            self.delay.delay_ms(options.poll_period_ms).await;
+        }
+    }
+
+
+    // Updates the ambient pressure used for compensation without
+    // interrupting continuous measurement.
+    pub async fn set_ambient_pressure(&mut self, pressure: Option<AmbientPressure>) -> Result<(), Error<E>> {
+        self.write_command_with_argument(CMD_START_CONTINUOUS_MEASUREMENT, pressure_argument(pressure)).await
+    }
+
+
+    // Values outside the range accepted by the sensor get rejected without
+    // talking to it.
+    pub async fn set_forced_recalibration_value(&mut self, co2_ppm: u16) -> Result<(), Error<E>> {
+        check_forced_recalibration_value(co2_ppm)?;
+        self.write_command_with_argument(CMD_FORCED_RECALIBRATION_VALUE, co2_ppm).await
+    }
+
+
+    pub async fn stop_continuous_measurement(self) -> Result<Scd30<I2C, D, Idle>, ModeChangeError<Error<E>, Self>> {
+        let mut sensor = self;
+
+        match sensor.write_command(CMD_STOP_CONTINUOUS_MEASUREMENT).await {
+            Ok(()) => Ok(sensor.into_mode()),
+            Err(error) => Err(ModeChangeError{ error, sensor }),
+        }
+    }
+}
+
+
+// Querying the sensor's state and configuration is possible in any mode.
+impl<I2C, D, E, MODE> Scd30<I2C, D, MODE> where I2C: I2c<Error = E>, D: DelayNs {
+    // Gives back the I2C bus and the delay.
+    pub fn release(self) -> (I2C, D) {
+        (self.i2c, self.delay)
+    }
+
+
+    pub async fn get_firmware_version(&mut self) -> Result<FirmwareVersion, Error<E>> {
+        let mut response = [0u8; WORD_RESPONSE_LEN];
+
+        self.read_response(CMD_READ_FIRMWARE_VERSION, &mut response).await?;
+        decode_firmware_version(&response)
+    }
+
+
+    pub async fn get_measurement_interval(&mut self) -> Result<MeasurementInterval, Error<E>> {
+        let seconds = self.read_word(CMD_MEASUREMENT_INTERVAL).await?;
+        Ok(MeasurementInterval(seconds))
+    }
+
+
+    pub async fn get_automatic_self_calibration(&mut self) -> Result<bool, Error<E>> {
+        let active = self.read_word(CMD_AUTOMATIC_SELF_CALIBRATION).await?;
+        Ok(active == 1u16)
+    }
+
+
+    pub async fn get_forced_recalibration_value(&mut self) -> Result<u16, Error<E>> {
+        self.read_word(CMD_FORCED_RECALIBRATION_VALUE).await
+    }
+
+
+    pub async fn get_temperature_offset(&mut self) -> Result<TemperatureOffset, Error<E>> {
+        let centikelvin = self.read_word(CMD_TEMPERATURE_OFFSET).await?;
+        Ok(TemperatureOffset(centikelvin))
+    }
+
+
+    pub async fn get_altitude_compensation(&mut self) -> Result<Altitude, Error<E>> {
+        let meters = self.read_word(CMD_ALTITUDE_COMPENSATION).await?;
+        Ok(Altitude(meters))
+    }
+
+
+    fn into_mode<NEW>(self) -> Scd30<I2C, D, NEW> {
+        Scd30{ i2c: self.i2c, delay: self.delay, mode: PhantomData }
+    }
+
+
+    async fn write_command(&mut self, command: u16) -> Result<(), Error<E>> {
+        let frame = command_frame(command);
+        defmt::trace!("command: {=[u8]}", frame);
+
+        self.i2c.write(I2C_ADDRESS, &frame).await?;
+        Ok(())
+    }
+
+
+    async fn write_command_with_argument(&mut self, command: u16, argument: u16) -> Result<(), Error<E>> {
+        let frame = command_with_argument_frame(command, argument);
+        defmt::trace!("command: {=[u8]}", frame);
+
+        self.i2c.write(I2C_ADDRESS, &frame).await?;
+        Ok(())
+    }
+
+
+    async fn read_response(&mut self, command: u16, response: &mut [u8]) -> Result<(), Error<E>> {
+        self.write_command(command).await?;
+        self.delay.delay_us(COMMAND_RESPONSE_PAUSE_US).await;
+        self.i2c.read(I2C_ADDRESS, response).await?;
+        defmt::trace!("response: {=[u8]}", response);
+
+        Ok(())
+    }
+
+
+    async fn read_word(&mut self, command: u16) -> Result<u16, Error<E>> {
+        let mut response = [0u8; WORD_RESPONSE_LEN];
+
+        self.read_response(command, &mut response).await?;
+        decode_word(&response)
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+
+    // The mocks complete immediately, so there is no need for a real
+    // executor.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+
+    #[test]
+    fn start_measure_and_stop() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x00, 0x10, 0x03, 0xfc, 0x53]),
+            Transaction::write(I2C_ADDRESS, vec![0x02, 0x02]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x01, 0xb0]),
+            Transaction::write(I2C_ADDRESS, vec![0x03, 0x00]),
+            Transaction::read(I2C_ADDRESS, vec![
+                0x43, 0xdb, 0xcb, 0x8c, 0x2e, 0x8f,
+                0x41, 0xd9, 0x70, 0xe7, 0xff, 0xf5,
+                0x42, 0x43, 0xbf, 0x3a, 0x1b, 0x74,
+            ]),
+            Transaction::write(I2C_ADDRESS, vec![0x01, 0x04]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let sensor = Scd30::new(i2c.clone(), NoopDelay::new());
+
+        block_on(async {
+            let mut sensor = sensor.start_continuous_measurement(AmbientPressure::from_mbar(1020)).await.unwrap();
+            assert_eq!(sensor.is_measurement_ready().await, Ok(true));
+            let measurement = sensor.get_measurement().await.unwrap();
+            assert_eq!(measurement.co2_ppm, 439.09515);
+            sensor.stop_continuous_measurement().await.unwrap();
+        });
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn wait_for_measurement_polls_until_ready() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x02, 0x02]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x00, 0x81]),
+            Transaction::write(I2C_ADDRESS, vec![0x02, 0x02]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x01, 0xb0]),
+            Transaction::write(I2C_ADDRESS, vec![0x03, 0x00]),
+            Transaction::read(I2C_ADDRESS, vec![
+                0x43, 0xdb, 0xcb, 0x8c, 0x2e, 0x8f,
+                0x41, 0xd9, 0x70, 0xe7, 0xff, 0xf5,
+                0x42, 0x43, 0xbf, 0x3a, 0x1b, 0x74,
+            ]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let sensor: Scd30<_, _, Measuring> = Scd30{ i2c: i2c.clone(), delay: NoopDelay::new(), mode: PhantomData };
+
+        block_on(async {
+            let mut sensor = sensor;
+            let measurement = sensor.wait_for_measurement(&WaitOptions::default()).await.unwrap();
+            assert_eq!(measurement.co2_ppm, 439.09515);
+        });
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn set_forced_recalibration_value_rejects_out_of_range_values() {
+        let mut i2c = Mock::new(&[]);
+        let sensor: Scd30<_, _, Measuring> = Scd30{ i2c: i2c.clone(), delay: NoopDelay::new(), mode: PhantomData };
+
+        block_on(async {
+            let mut sensor = sensor;
+            assert_eq!(sensor.set_forced_recalibration_value(399).await, Err(Error::InvalidArgument{ command: CMD_FORCED_RECALIBRATION_VALUE, argument: 399 }));
+        });
+
+        i2c.done();
+    }
+}
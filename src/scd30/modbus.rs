@@ -0,0 +1,369 @@
+// A variant of the SCD30 driver talking Modbus RTU over the sensor's UART
+// pins. It mirrors the I2C driver from the parent module and shares
+// measurement decoding and value validation with it.
+//
+// See 'Interface Description Sensirion SCD30 Sensor Module', section 2
+// 'Modbus Interface' for the register map.
+
+
+use core::marker::PhantomData;
+use embedded_hal_1::delay::DelayNs;
+use embedded_io::{Read, ReadReady, Write};
+
+use crate::modbus::{self, ModbusRtu};
+use super::{
+    Altitude,
+    AmbientPressure,
+    FirmwareVersion,
+    Idle,
+    Measurement,
+    MeasurementInterval,
+    Measuring,
+    ModeChangeError,
+    TemperatureOffset,
+    FRC_MAX_PPM,
+    FRC_MIN_PPM,
+};
+use super::protocol::{
+    find_invalid_value,
+    measurement_from_words,
+    pressure_argument,
+};
+
+
+
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error<E> {
+    // An argument outside the range accepted by the sensor got rejected
+    // without talking to it. Carries the register and the rejected argument.
+    InvalidArgument{ register: u16, argument: u16 },
+    // A measurement value is not a number or infinite. Carries the index of
+    // the value's first word.
+    InvalidValue{ word: u8 },
+    // Errors from Modbus communication like CRC errors or exception
+    // responses from the sensor.
+    ModbusError(modbus::Error<E>),
+}
+
+
+pub struct Scd30<UART: Read + ReadReady + Write, D: DelayNs, MODE = Idle> {
+    modbus: ModbusRtu<UART, D>,
+    mode: PhantomData<MODE>,
+}
+
+
+
+
+pub const MODBUS_ADDRESS: u8 = 0x61;
+pub const BAUD_RATE: u32 = 19_200;
+
+
+// Register addresses from section 2.4 'Modbus Commands'.
+const REG_FIRMWARE_VERSION: u16 = 0x0020;
+const REG_MEASUREMENT_INTERVAL: u16 = 0x0025;
+const REG_GET_DATA_READY: u16 = 0x0027;
+const REG_READ_MEASUREMENT: u16 = 0x0028;
+const REG_SOFT_RESET: u16 = 0x0034;
+const REG_START_CONTINUOUS_MEASUREMENT: u16 = 0x0036;
+const REG_STOP_CONTINUOUS_MEASUREMENT: u16 = 0x0037;
+const REG_ALTITUDE_COMPENSATION: u16 = 0x0038;
+const REG_FORCED_RECALIBRATION_VALUE: u16 = 0x0039;
+const REG_AUTOMATIC_SELF_CALIBRATION: u16 = 0x003a;
+const REG_TEMPERATURE_OFFSET: u16 = 0x003b;
+
+
+
+
+// Allow automatic conversion from Modbus errors to the driver's error type
+// (for the question mark operator).
+impl<E> From<modbus::Error<E>> for Error<E> {
+    fn from(err: modbus::Error<E>) -> Error<E> {
+        Error::ModbusError(err)
+    }
+}
+
+
+// Starting measurement and changing the sensor's persistent configuration is
+// only possible while it is not measuring. This keeps a running series of
+// measurements consistent.
+impl<UART, D, E> Scd30<UART, D, Idle> where UART: Read<Error = E> + ReadReady<Error = E> + Write<Error = E>, D: DelayNs {
+    // The UART needs to be configured for 19200 baud, 8 data bits, no parity
+    // and one stop bit. The driver takes ownership of a delay for keeping the
+    // silent interval between Modbus frames.
+    pub fn new(uart: UART, delay: D) -> Self {
+        Scd30{ modbus: ModbusRtu::new(uart, delay, BAUD_RATE), mode: PhantomData }
+    }
+
+
+    // Passing an ambient pressure enables pressure compensation. Otherwise,
+    // the sensor compensates for the configured altitude.
+    pub fn start_continuous_measurement(self, pressure: Option<AmbientPressure>) -> Result<Scd30<UART, D, Measuring>, ModeChangeError<Error<E>, Self>> {
+        let mut sensor = self;
+
+        match sensor.write_register(REG_START_CONTINUOUS_MEASUREMENT, pressure_argument(pressure)) {
+            Ok(()) => Ok(sensor.into_mode()),
+            Err(error) => Err(ModeChangeError{ error, sensor }),
+        }
+    }
+
+
+    pub fn set_measurement_interval(&mut self, interval: MeasurementInterval) -> Result<(), Error<E>> {
+        self.write_register(REG_MEASUREMENT_INTERVAL, interval.seconds())
+    }
+
+
+    // Turns automatic self-calibration (ASC) on or off.
+    pub fn set_automatic_self_calibration(&mut self, active: bool) -> Result<(), Error<E>> {
+        self.write_register(REG_AUTOMATIC_SELF_CALIBRATION, active as u16)
+    }
+
+
+    pub fn set_temperature_offset(&mut self, offset: TemperatureOffset) -> Result<(), Error<E>> {
+        self.write_register(REG_TEMPERATURE_OFFSET, offset.centikelvin())
+    }
+
+
+    pub fn set_altitude_compensation(&mut self, altitude: Altitude) -> Result<(), Error<E>> {
+        self.write_register(REG_ALTITUDE_COMPENSATION, altitude.meters())
+    }
+
+
+    pub fn soft_reset(&mut self) -> Result<(), Error<E>> {
+        self.write_register(REG_SOFT_RESET, 0x0001)
+    }
+}
+
+
+// Reading measurements and forced recalibration require the sensor to
+// measure continuously.
+impl<UART, D, E> Scd30<UART, D, Measuring> where UART: Read<Error = E> + ReadReady<Error = E> + Write<Error = E>, D: DelayNs {
+    pub fn get_measurement(&mut self) -> Result<Measurement, Error<E>> {
+        let mut words = [0u16; 6];
+
+        self.modbus.read_holding_registers(MODBUS_ADDRESS, REG_READ_MEASUREMENT, &mut words)?;
+        let measurement = measurement_from_words(words);
+        match find_invalid_value(&measurement) {
+            Some(word) => Err(Error::InvalidValue{ word }),
+            None => Ok(measurement),
+        }
+    }
+
+
+    pub fn is_measurement_ready(&mut self) -> Result<bool, Error<E>> {
+        let ready = self.read_register(REG_GET_DATA_READY)?;
+        Ok(ready == 1u16)
+    }
+
+
+    // Updates the ambient pressure used for compensation without
+    // interrupting continuous measurement.
+    pub fn set_ambient_pressure(&mut self, pressure: Option<AmbientPressure>) -> Result<(), Error<E>> {
+        self.write_register(REG_START_CONTINUOUS_MEASUREMENT, pressure_argument(pressure))
+    }
+
+
+    // Values outside the range accepted by the sensor get rejected without
+    // talking to it.
+    pub fn set_forced_recalibration_value(&mut self, co2_ppm: u16) -> Result<(), Error<E>> {
+        if !(FRC_MIN_PPM..=FRC_MAX_PPM).contains(&co2_ppm) {
+            return Err(Error::InvalidArgument{ register: REG_FORCED_RECALIBRATION_VALUE, argument: co2_ppm });
+        }
+        self.write_register(REG_FORCED_RECALIBRATION_VALUE, co2_ppm)
+    }
+
+
+    pub fn stop_continuous_measurement(self) -> Result<Scd30<UART, D, Idle>, ModeChangeError<Error<E>, Self>> {
+        let mut sensor = self;
+
+        match sensor.write_register(REG_STOP_CONTINUOUS_MEASUREMENT, 0x0001) {
+            Ok(()) => Ok(sensor.into_mode()),
+            Err(error) => Err(ModeChangeError{ error, sensor }),
+        }
+    }
+}
+
+
+// Querying the sensor's state and configuration is possible in any mode.
+impl<UART, D, E, MODE> Scd30<UART, D, MODE> where UART: Read<Error = E> + ReadReady<Error = E> + Write<Error = E>, D: DelayNs {
+    // Gives back the UART and the delay.
+    pub fn release(self) -> (UART, D) {
+        self.modbus.release()
+    }
+
+
+    pub fn get_firmware_version(&mut self) -> Result<FirmwareVersion, Error<E>> {
+        let [major, minor] = self.read_register(REG_FIRMWARE_VERSION)?.to_be_bytes();
+        Ok(FirmwareVersion{ major, minor })
+    }
+
+
+    pub fn get_measurement_interval(&mut self) -> Result<MeasurementInterval, Error<E>> {
+        let seconds = self.read_register(REG_MEASUREMENT_INTERVAL)?;
+        Ok(MeasurementInterval(seconds))
+    }
+
+
+    pub fn get_automatic_self_calibration(&mut self) -> Result<bool, Error<E>> {
+        let active = self.read_register(REG_AUTOMATIC_SELF_CALIBRATION)?;
+        Ok(active == 1u16)
+    }
+
+
+    pub fn get_forced_recalibration_value(&mut self) -> Result<u16, Error<E>> {
+        self.read_register(REG_FORCED_RECALIBRATION_VALUE)
+    }
+
+
+    pub fn get_temperature_offset(&mut self) -> Result<TemperatureOffset, Error<E>> {
+        let centikelvin = self.read_register(REG_TEMPERATURE_OFFSET)?;
+        Ok(TemperatureOffset(centikelvin))
+    }
+
+
+    pub fn get_altitude_compensation(&mut self) -> Result<Altitude, Error<E>> {
+        let meters = self.read_register(REG_ALTITUDE_COMPENSATION)?;
+        Ok(Altitude(meters))
+    }
+
+
+    fn into_mode<NEW>(self) -> Scd30<UART, D, NEW> {
+        Scd30{ modbus: self.modbus, mode: PhantomData }
+    }
+
+
+    fn read_register(&mut self, address: u16) -> Result<u16, Error<E>> {
+        let mut value = [0u16; 1];
+
+        self.modbus.read_holding_registers(MODBUS_ADDRESS, address, &mut value)?;
+        Ok(value[0])
+    }
+
+
+    fn write_register(&mut self, address: u16, value: u16) -> Result<(), Error<E>> {
+        self.modbus.write_single_register(MODBUS_ADDRESS, address, value)?;
+        Ok(())
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modbus::testing::ScriptedPeer;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+
+    fn measuring<UART: Read + ReadReady + Write>(uart: UART) -> Scd30<UART, NoopDelay, Measuring> {
+        Scd30{ modbus: ModbusRtu::new(uart, NoopDelay::new(), BAUD_RATE), mode: PhantomData }
+    }
+
+
+    #[test]
+    fn start_and_stop_continuous_measurement() {
+        // Examples from the interface description.
+        let mut peer = ScriptedPeer::new(&[
+            (
+                &[0x61, 0x06, 0x00, 0x36, 0x00, 0x00, 0x60, 0x64],
+                &[0x61, 0x06, 0x00, 0x36, 0x00, 0x00, 0x60, 0x64],
+            ),
+            (
+                &[0x61, 0x06, 0x00, 0x37, 0x00, 0x01, 0xf0, 0x64],
+                &[0x61, 0x06, 0x00, 0x37, 0x00, 0x01, 0xf0, 0x64],
+            ),
+        ]);
+
+        let sensor = Scd30::new(&mut peer, NoopDelay::new());
+        let sensor = sensor.start_continuous_measurement(None).unwrap();
+        sensor.stop_continuous_measurement().unwrap();
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn get_measurement() {
+        // Example from the interface description.
+        let mut peer = ScriptedPeer::new(&[
+            (
+                &[0x61, 0x03, 0x00, 0x27, 0x00, 0x01, 0x3d, 0xa1],
+                &[0x61, 0x03, 0x02, 0x00, 0x01, 0xf9, 0x8c],
+            ),
+            (
+                &[0x61, 0x03, 0x00, 0x28, 0x00, 0x06, 0x4c, 0x60],
+                &[
+                    0x61, 0x03, 0x0c,
+                    0x43, 0xdb, 0x8c, 0x2e,
+                    0x41, 0xd9, 0xe7, 0xff,
+                    0x42, 0x43, 0x3a, 0x1b,
+                    0x50, 0x07,
+                ],
+            ),
+        ]);
+
+        let mut sensor = measuring(&mut peer);
+        assert_eq!(sensor.is_measurement_ready(), Ok(true));
+        let measurement = sensor.get_measurement().unwrap();
+        assert_eq!(measurement.co2_ppm, 439.09515);
+        assert_eq!(measurement.temperature_celsius, Some(27.23828));
+        assert_eq!(measurement.humidity_percent, Some(48.806744));
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn configuration() {
+        let mut peer = ScriptedPeer::new(&[
+            (
+                &[0x61, 0x06, 0x00, 0x25, 0x00, 0x02, 0x10, 0x60],
+                &[0x61, 0x06, 0x00, 0x25, 0x00, 0x02, 0x10, 0x60],
+            ),
+            (
+                &[0x61, 0x03, 0x00, 0x3a, 0x00, 0x01, 0xad, 0xa7],
+                &[0x61, 0x03, 0x02, 0x00, 0x01, 0xf9, 0x8c],
+            ),
+            (
+                &[0x61, 0x03, 0x00, 0x20, 0x00, 0x01, 0x8c, 0x60],
+                &[0x61, 0x03, 0x02, 0x03, 0x42, 0xb8, 0x8d],
+            ),
+        ]);
+
+        let mut sensor = Scd30::new(&mut peer, NoopDelay::new());
+        sensor.set_measurement_interval(MeasurementInterval::from_seconds(2).unwrap()).unwrap();
+        assert_eq!(sensor.get_automatic_self_calibration(), Ok(true));
+        let version = sensor.get_firmware_version().unwrap();
+        assert_eq!((version.major, version.minor), (3, 66));
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn set_forced_recalibration_value_rejects_out_of_range_values() {
+        let mut peer = ScriptedPeer::new(&[]);
+
+        let mut sensor = measuring(&mut peer);
+        assert_eq!(sensor.set_forced_recalibration_value(2001), Err(Error::InvalidArgument{ register: REG_FORCED_RECALIBRATION_VALUE, argument: 2001 }));
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn reports_exception_response() {
+        // Illegal data value.
+        let mut peer = ScriptedPeer::new(&[(
+            &[0x61, 0x06, 0x00, 0x39, 0x01, 0xc2, 0xd0, 0x66],
+            &[0x61, 0x86, 0x03, 0x02, 0x7f],
+        )]);
+
+        let mut sensor = measuring(&mut peer);
+        assert_eq!(sensor.set_forced_recalibration_value(450),
+            Err(Error::ModbusError(modbus::Error::Exception(0x03))));
+
+        peer.done();
+    }
+}
@@ -0,0 +1,189 @@
+// The SCD30 protocol without any I/O: command words, their arguments and
+// decoding responses. Commands get framed by crate::sensirion. This is shared by all
+// transports and does not allocate.
+//
+// See 'Interface Description Sensirion SCD30 Sensor Module' for the details.
+
+
+use crate::sensirion::{decode_words, frame_len};
+
+use super::{
+    AmbientPressure,
+    Error,
+    FirmwareVersion,
+    Measurement,
+    FRC_MAX_PPM,
+    FRC_MIN_PPM,
+};
+
+
+
+
+// Command words from section 1.4 'Sensor Commands'.
+pub const CMD_START_CONTINUOUS_MEASUREMENT: u16 = 0x0010;
+pub const CMD_STOP_CONTINUOUS_MEASUREMENT: u16 = 0x0104;
+pub const CMD_MEASUREMENT_INTERVAL: u16 = 0x4600;
+pub const CMD_AUTOMATIC_SELF_CALIBRATION: u16 = 0x5306;
+pub const CMD_FORCED_RECALIBRATION_VALUE: u16 = 0x5204;
+pub const CMD_TEMPERATURE_OFFSET: u16 = 0x5403;
+pub const CMD_ALTITUDE_COMPENSATION: u16 = 0x5102;
+pub const CMD_GET_DATA_READY: u16 = 0x0202;
+pub const CMD_READ_MEASUREMENT: u16 = 0x0300;
+pub const CMD_READ_FIRMWARE_VERSION: u16 = 0xd100;
+pub const CMD_SOFT_RESET: u16 = 0xd304;
+
+
+// A response consists of words with a trailing CRC each.
+pub const WORD_RESPONSE_LEN: usize = frame_len(1);
+pub const MEASUREMENT_RESPONSE_LEN: usize = frame_len(6);
+
+
+
+
+// Checks the CRC of a single word from a response and returns the word.
+pub fn decode_word<E>(response: &[u8; WORD_RESPONSE_LEN]) -> Result<u16, Error<E>> {
+    let [word] = decode_words(response).map_err(Error::CrcError)?;
+    Ok(word)
+}
+
+
+pub fn decode_data_ready<E>(response: &[u8; WORD_RESPONSE_LEN]) -> Result<bool, Error<E>> {
+    let ready = decode_word(response)?;
+    Ok(ready == 1u16)
+}
+
+
+pub fn decode_firmware_version<E>(response: &[u8; WORD_RESPONSE_LEN]) -> Result<FirmwareVersion, Error<E>> {
+    let [major, minor] = decode_word(response)?.to_be_bytes();
+    Ok(FirmwareVersion{ major, minor })
+}
+
+
+pub fn decode_measurement<E>(response: &[u8; MEASUREMENT_RESPONSE_LEN]) -> Result<Measurement, Error<E>> {
+    let words = decode_words(response).map_err(Error::CrcError)?;
+
+    let measurement = measurement_from_words(words);
+    match find_invalid_value(&measurement) {
+        Some(word) => Err(Error::InvalidValue{ word }),
+        None => Ok(measurement),
+    }
+}
+
+
+// Assembles a measurement from the six words transmitted by the sensor. Each
+// value is a big-endian float split into a high and a low word. This is the
+// part of decoding a measurement which does not depend on how the words got
+// transmitted.
+pub fn measurement_from_words(words: [u16; 6]) -> Measurement {
+    let to_f32 = |high: u16, low: u16| f32::from_bits((high as u32) << 16 | low as u32);
+
+    Measurement {
+        co2_ppm: to_f32(words[0], words[1]),
+        temperature_celsius: Some(to_f32(words[2], words[3])),
+        humidity_percent: Some(to_f32(words[4], words[5])),
+    }
+}
+
+
+// Returns the index of the first word of the first value which is not a
+// number or infinite. Such values are never sent by a properly working sensor
+// but show up with garbled frames whose CRC happens to match.
+pub fn find_invalid_value(measurement: &Measurement) -> Option<u8> {
+    let values = [Some(measurement.co2_ppm), measurement.temperature_celsius, measurement.humidity_percent];
+
+    values.iter()
+        .position(|value| matches!(value, Some(value) if !value.is_finite()))
+        .map(|index| 2 * index as u8)
+}
+
+
+// The argument for starting continuous measurement. Zero disables ambient
+// pressure compensation.
+pub fn pressure_argument(pressure: Option<AmbientPressure>) -> u16 {
+    pressure.map_or(0, |pressure| pressure.mbar())
+}
+
+
+pub fn check_forced_recalibration_value<E>(co2_ppm: u16) -> Result<(), Error<E>> {
+    if (FRC_MIN_PPM..=FRC_MAX_PPM).contains(&co2_ppm) {
+        Ok(())
+    } else {
+        Err(Error::<E>::InvalidArgument{ command: CMD_FORCED_RECALIBRATION_VALUE, argument: co2_ppm })
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensirion::CrcError;
+
+
+    // Example response from section 1.5 'Read measurement'.
+    const MEASUREMENT_RESPONSE: [u8; MEASUREMENT_RESPONSE_LEN] = [
+        0x43, 0xdb, 0xcb, 0x8c, 0x2e, 0x8f,
+        0x41, 0xd9, 0x70, 0xe7, 0xff, 0xf5,
+        0x42, 0x43, 0xbf, 0x3a, 0x1b, 0x74,
+    ];
+
+
+    #[test]
+    fn decode_word_checks_crc() {
+        assert_eq!(decode_word::<()>(&[0xbe, 0xef, 0x92]), Ok(0xbeef));
+        assert_eq!(decode_word::<()>(&[0xbe, 0xef, 0x93]), Err(Error::CrcError(CrcError{ word: 0, received: 0x93, expected: 0x92 })));
+    }
+
+
+    #[test]
+    fn decode_data_ready_and_firmware_version() {
+        assert_eq!(decode_data_ready::<()>(&[0x00, 0x01, 0xb0]), Ok(true));
+        assert_eq!(decode_data_ready::<()>(&[0x00, 0x00, 0x81]), Ok(false));
+
+        let version = decode_firmware_version::<()>(&[0x03, 0x42, 0xf3]).unwrap();
+        assert_eq!((version.major, version.minor), (3, 66));
+    }
+
+
+    #[test]
+    fn decode_measurement_matches_example() {
+        let measurement = decode_measurement::<()>(&MEASUREMENT_RESPONSE).unwrap();
+
+        assert_eq!(measurement.co2_ppm, 439.09515);
+        assert_eq!(measurement.temperature_celsius, Some(27.23828));
+        assert_eq!(measurement.humidity_percent, Some(48.806744));
+    }
+
+
+    #[test]
+    fn decode_measurement_detects_crc_error_in_any_word() {
+        for (word, index) in (2..MEASUREMENT_RESPONSE_LEN).step_by(WORD_RESPONSE_LEN).enumerate() {
+            let mut response = MEASUREMENT_RESPONSE;
+            let expected = response[index];
+            response[index] ^= 0x01;
+
+            assert_eq!(decode_measurement::<()>(&response),
+                Err(Error::CrcError(CrcError{ word: word as u8, received: expected ^ 0x01, expected })));
+        }
+    }
+
+
+    #[test]
+    fn decode_measurement_rejects_nan() {
+        // A quiet NaN as temperature.
+        let mut response = MEASUREMENT_RESPONSE;
+        response[6..12].copy_from_slice(&[0x7f, 0xc0, 0x64, 0x00, 0x00, 0x81]);
+
+        assert_eq!(decode_measurement::<()>(&response), Err(Error::InvalidValue{ word: 2 }));
+    }
+
+
+    #[test]
+    fn check_forced_recalibration_value_range() {
+        assert_eq!(check_forced_recalibration_value::<()>(399), Err(Error::InvalidArgument{ command: CMD_FORCED_RECALIBRATION_VALUE, argument: 399 }));
+        assert_eq!(check_forced_recalibration_value::<()>(400), Ok(()));
+        assert_eq!(check_forced_recalibration_value::<()>(2000), Ok(()));
+        assert_eq!(check_forced_recalibration_value::<()>(2001), Err(Error::InvalidArgument{ command: CMD_FORCED_RECALIBRATION_VALUE, argument: 2001 }));
+    }
+}
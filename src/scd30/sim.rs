@@ -0,0 +1,519 @@
+// A simulated SCD30 for running the drivers and applications built on them
+// without hardware. It decodes the commands sent to it over I2C, keeps the
+// sensor's state and answers with properly framed responses. Measurements
+// follow scripted waveforms and faults can be injected for testing error
+// handling.
+//
+// The simulation is driven by advancing its clock explicitly. It hands out
+// I2C devices borrowing it, so it can be inspected and manipulated while a
+// driver owns such a device.
+
+
+use core::cell::RefCell;
+use embedded_hal_1::i2c::{self, ErrorKind, ErrorType, NoAcknowledgeSource, Operation};
+
+use super::{
+    Measurement,
+    MeasurementInterval,
+    I2C_ADDRESS,
+};
+use super::protocol::{
+    CMD_ALTITUDE_COMPENSATION,
+    CMD_AUTOMATIC_SELF_CALIBRATION,
+    CMD_FORCED_RECALIBRATION_VALUE,
+    CMD_GET_DATA_READY,
+    CMD_MEASUREMENT_INTERVAL,
+    CMD_READ_FIRMWARE_VERSION,
+    CMD_READ_MEASUREMENT,
+    CMD_SOFT_RESET,
+    CMD_START_CONTINUOUS_MEASUREMENT,
+    CMD_STOP_CONTINUOUS_MEASUREMENT,
+    CMD_TEMPERATURE_OFFSET,
+    MEASUREMENT_RESPONSE_LEN,
+    WORD_RESPONSE_LEN,
+};
+use crate::sensirion::{crc, encode_words};
+
+
+
+
+// The values a simulated quantity takes on for subsequent measurements.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Constant(f32),
+    // Starts at the given value and changes by step with every measurement.
+    Ramp{ start: f32, step: f32 },
+    // Cycles through the given values.
+    Samples(&'static [f32]),
+}
+
+
+// Faults to inject into the communication with the simulated sensor. They
+// stay active until cleared.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Fault {
+    // Corrupts the CRC of the first word of every response.
+    CorruptCrc,
+    // Does not acknowledge any transfer.
+    Nack,
+    // Reports the given data-ready state regardless of new measurements.
+    StuckDataReady(bool),
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SimError {
+    // The simulated sensor did not acknowledge its address. Either because of
+    // an injected fault or because of talking to a different address.
+    Nack,
+    // A transfer the sensor would not understand: an unknown command, an
+    // argument with a wrong CRC or reading without a preceding command.
+    UnexpectedTransfer,
+}
+
+
+// A snapshot of the simulated sensor's state.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SimState {
+    pub measuring: bool,
+    pub pressure_mbar: u16,
+    pub interval_seconds: u16,
+    pub asc_active: bool,
+    pub frc_ppm: u16,
+    pub temperature_offset_centikelvin: u16,
+    pub altitude_meters: u16,
+    pub data_ready: bool,
+}
+
+
+pub struct Scd30Sim {
+    inner: RefCell<Inner>,
+}
+
+
+// An I2C device for talking to the simulated sensor.
+pub struct SimI2c<'a> {
+    sim: &'a Scd30Sim,
+}
+
+
+struct Inner {
+    state: SimState,
+    co2_ppm: Waveform,
+    temperature_celsius: Waveform,
+    humidity_percent: Waveform,
+    samples: u32,
+    elapsed_seconds: u32,
+    latest: Measurement,
+    response: [u8; MEASUREMENT_RESPONSE_LEN],
+    response_len: usize,
+    fault: Option<Fault>,
+}
+
+
+
+
+// The firmware version reported by the simulated sensor.
+pub const FIRMWARE_VERSION: (u8, u8) = (3, 66);
+
+// The reference value for forced recalibration reported after power-up.
+const DEFAULT_FRC_PPM: u16 = 400;
+
+
+
+
+impl Waveform {
+    pub fn value(&self, sample: u32) -> f32 {
+        match *self {
+            Waveform::Constant(value) => value,
+            Waveform::Ramp{ start, step } => start + step * sample as f32,
+            Waveform::Samples(values) => values[sample as usize % values.len()],
+        }
+    }
+}
+
+
+impl i2c::Error for SimError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            SimError::Nack => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address),
+            SimError::UnexpectedTransfer => ErrorKind::Other,
+        }
+    }
+}
+
+
+impl Scd30Sim {
+    // Creates a sensor which is powered up and not measuring. Measurements
+    // take their values from the given waveforms.
+    pub fn new(co2_ppm: Waveform, temperature_celsius: Waveform, humidity_percent: Waveform) -> Self {
+        let state = SimState {
+            measuring: false,
+            pressure_mbar: 0,
+            interval_seconds: MeasurementInterval::default().seconds(),
+            asc_active: false,
+            frc_ppm: DEFAULT_FRC_PPM,
+            temperature_offset_centikelvin: 0,
+            altitude_meters: 0,
+            data_ready: false,
+        };
+        let latest = Measurement{ co2_ppm: 0.0, temperature_celsius: Some(0.0), humidity_percent: Some(0.0) };
+
+        Scd30Sim{ inner: RefCell::new(Inner {
+            state,
+            co2_ppm,
+            temperature_celsius,
+            humidity_percent,
+            samples: 0,
+            elapsed_seconds: 0,
+            latest,
+            response: [0u8; MEASUREMENT_RESPONSE_LEN],
+            response_len: 0,
+            fault: None,
+        })}
+    }
+
+
+    pub fn i2c(&self) -> SimI2c<'_> {
+        SimI2c{ sim: self }
+    }
+
+
+    pub fn state(&self) -> SimState {
+        self.inner.borrow().state
+    }
+
+
+    // Lets the given time pass. While measuring, a new measurement becomes
+    // ready whenever the measurement interval has elapsed.
+    pub fn advance_seconds(&self, seconds: u32) {
+        let mut inner = self.inner.borrow_mut();
+
+        if !inner.state.measuring {
+            return;
+        }
+
+        inner.elapsed_seconds += seconds;
+        while inner.elapsed_seconds >= inner.state.interval_seconds as u32 {
+            inner.elapsed_seconds -= inner.state.interval_seconds as u32;
+            inner.measure();
+        }
+    }
+
+
+    pub fn set_fault(&self, fault: Option<Fault>) {
+        self.inner.borrow_mut().fault = fault;
+    }
+
+
+    fn write(&self, address: u8, bytes: &[u8]) -> Result<(), SimError> {
+        let mut inner = self.inner.borrow_mut();
+
+        inner.check_ack(address)?;
+        inner.response_len = 0;
+
+        match *bytes {
+            [high, low] => inner.command(u16::from_be_bytes([high, low])),
+            [high, low, argument_high, argument_low, argument_crc] => {
+                if argument_crc != crc(&[argument_high, argument_low]) {
+                    return Err(SimError::UnexpectedTransfer);
+                }
+                let argument = u16::from_be_bytes([argument_high, argument_low]);
+                inner.command_with_argument(u16::from_be_bytes([high, low]), argument)
+            }
+            _ => Err(SimError::UnexpectedTransfer),
+        }
+    }
+
+
+    fn read(&self, address: u8, buffer: &mut [u8]) -> Result<(), SimError> {
+        let mut inner = self.inner.borrow_mut();
+
+        inner.check_ack(address)?;
+        if buffer.is_empty() || buffer.len() > inner.response_len {
+            return Err(SimError::UnexpectedTransfer);
+        }
+
+        buffer.copy_from_slice(&inner.response[..buffer.len()]);
+        if inner.fault == Some(Fault::CorruptCrc) && buffer.len() >= WORD_RESPONSE_LEN {
+            buffer[WORD_RESPONSE_LEN - 1] ^= 0x01;
+        }
+        inner.response_len = 0;
+
+        Ok(())
+    }
+}
+
+
+impl Inner {
+    fn check_ack(&self, address: u8) -> Result<(), SimError> {
+        if address != I2C_ADDRESS || self.fault == Some(Fault::Nack) {
+            Err(SimError::Nack)
+        } else {
+            Ok(())
+        }
+    }
+
+
+    fn measure(&mut self) {
+        let sample = self.samples;
+        let offset_celsius = self.state.temperature_offset_centikelvin as f32 / 100.0;
+
+        self.latest = Measurement {
+            co2_ppm: self.co2_ppm.value(sample),
+            temperature_celsius: Some(self.temperature_celsius.value(sample) - offset_celsius),
+            humidity_percent: Some(self.humidity_percent.value(sample)),
+        };
+        self.samples += 1;
+        self.state.data_ready = true;
+    }
+
+
+    fn command(&mut self, command: u16) -> Result<(), SimError> {
+        match command {
+            CMD_STOP_CONTINUOUS_MEASUREMENT => {
+                self.state.measuring = false;
+                self.state.data_ready = false;
+            }
+            CMD_SOFT_RESET => {
+                self.state.data_ready = false;
+                self.elapsed_seconds = 0;
+            }
+            CMD_GET_DATA_READY => {
+                let ready = match self.fault {
+                    Some(Fault::StuckDataReady(ready)) => ready,
+                    _ => self.state.data_ready,
+                };
+                self.respond_word(ready as u16);
+            }
+            CMD_READ_MEASUREMENT => {
+                let Measurement{ co2_ppm, temperature_celsius, humidity_percent } = self.latest;
+                let values = [co2_ppm, temperature_celsius.unwrap_or_default(), humidity_percent.unwrap_or_default()];
+
+                let mut words = [0u16; 6];
+                for (value, pair) in values.iter().zip(words.chunks_exact_mut(2)) {
+                    let bits = value.to_bits();
+                    pair.copy_from_slice(&[(bits >> 16) as u16, bits as u16]);
+                }
+                encode_words(&words, &mut self.response);
+                self.response_len = MEASUREMENT_RESPONSE_LEN;
+                self.state.data_ready = false;
+            }
+            CMD_READ_FIRMWARE_VERSION => {
+                let (major, minor) = FIRMWARE_VERSION;
+                self.respond_word(u16::from_be_bytes([major, minor]));
+            }
+            CMD_MEASUREMENT_INTERVAL => self.respond_word(self.state.interval_seconds),
+            CMD_AUTOMATIC_SELF_CALIBRATION => self.respond_word(self.state.asc_active as u16),
+            CMD_FORCED_RECALIBRATION_VALUE => self.respond_word(self.state.frc_ppm),
+            CMD_TEMPERATURE_OFFSET => self.respond_word(self.state.temperature_offset_centikelvin),
+            CMD_ALTITUDE_COMPENSATION => self.respond_word(self.state.altitude_meters),
+            _ => return Err(SimError::UnexpectedTransfer),
+        }
+
+        Ok(())
+    }
+
+
+    fn command_with_argument(&mut self, command: u16, argument: u16) -> Result<(), SimError> {
+        match command {
+            CMD_START_CONTINUOUS_MEASUREMENT => {
+                self.state.measuring = true;
+                self.state.pressure_mbar = argument;
+                self.elapsed_seconds = 0;
+            }
+            CMD_MEASUREMENT_INTERVAL => {
+                if MeasurementInterval::from_seconds(argument).is_none() {
+                    return Err(SimError::UnexpectedTransfer);
+                }
+                self.state.interval_seconds = argument;
+            }
+            CMD_AUTOMATIC_SELF_CALIBRATION => self.state.asc_active = argument != 0,
+            CMD_FORCED_RECALIBRATION_VALUE => self.state.frc_ppm = argument,
+            CMD_TEMPERATURE_OFFSET => self.state.temperature_offset_centikelvin = argument,
+            CMD_ALTITUDE_COMPENSATION => self.state.altitude_meters = argument,
+            _ => return Err(SimError::UnexpectedTransfer),
+        }
+
+        Ok(())
+    }
+
+
+    fn respond_word(&mut self, word: u16) {
+        encode_words(&[word], &mut self.response[..WORD_RESPONSE_LEN]);
+        self.response_len = WORD_RESPONSE_LEN;
+    }
+}
+
+
+impl ErrorType for SimI2c<'_> {
+    type Error = SimError;
+}
+
+
+impl i2c::I2c for SimI2c<'_> {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => self.sim.write(address, bytes)?,
+                Operation::Read(buffer) => self.sim.read(address, buffer)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(feature = "eh0")]
+impl embedded_hal::blocking::i2c::Write for SimI2c<'_> {
+    type Error = SimError;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.sim.write(address, bytes)
+    }
+}
+
+
+#[cfg(feature = "eh0")]
+impl embedded_hal::blocking::i2c::Read for SimI2c<'_> {
+    type Error = SimError;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.sim.read(address, buffer)
+    }
+}
+
+
+#[cfg(feature = "eh0")]
+impl embedded_hal::blocking::i2c::WriteRead for SimI2c<'_> {
+    type Error = SimError;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.sim.write(address, bytes)?;
+        self.sim.read(address, buffer)
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scd30::{Altitude, AmbientPressure, Error, Scd30, Scd30Config, TemperatureOffset};
+    use crate::sensirion::CrcError;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+
+    fn sim() -> Scd30Sim {
+        Scd30Sim::new(
+            Waveform::Ramp{ start: 400.0, step: 10.0 },
+            Waveform::Constant(22.5),
+            Waveform::Samples(&[40.0, 60.0]),
+        )
+    }
+
+
+    #[test]
+    fn measures_along_waveforms() {
+        let sim = sim();
+        let sensor = Scd30::new(sim.i2c(), NoopDelay::new());
+
+        let mut sensor = sensor.start_continuous_measurement(AmbientPressure::from_mbar(1013)).unwrap();
+        assert_eq!(sim.state().pressure_mbar, 1013);
+        assert_eq!(sensor.is_measurement_ready(), Ok(false));
+
+        sim.advance_seconds(2);
+        assert_eq!(sensor.is_measurement_ready(), Ok(true));
+        assert_eq!(sensor.get_measurement(), Ok(Measurement{ co2_ppm: 400.0, temperature_celsius: Some(22.5), humidity_percent: Some(40.0) }));
+        assert_eq!(sensor.is_measurement_ready(), Ok(false));
+
+        sim.advance_seconds(2);
+        assert_eq!(sensor.get_measurement(), Ok(Measurement{ co2_ppm: 410.0, temperature_celsius: Some(22.5), humidity_percent: Some(60.0) }));
+
+        sensor.stop_continuous_measurement().unwrap();
+        assert!(!sim.state().measuring);
+    }
+
+
+    #[test]
+    fn configure_stops_measurement_left_running() {
+        let sim = sim();
+        let previous = Scd30::new(sim.i2c(), NoopDelay::new());
+        previous.start_continuous_measurement(None).unwrap();
+        assert!(sim.state().measuring);
+
+        // A driver from after a reset of the microcontroller starts out as
+        // idle while the sensor is still measuring.
+        let mut sensor = Scd30::new(sim.i2c(), NoopDelay::new());
+        sensor.configure(&Scd30Config::default()).unwrap();
+        assert!(!sim.state().measuring);
+    }
+
+
+    #[test]
+    fn keeps_configuration() {
+        let sim = sim();
+        let mut sensor = Scd30::new(sim.i2c(), NoopDelay::new());
+
+        sensor.set_measurement_interval(MeasurementInterval::from_seconds(5).unwrap()).unwrap();
+        sensor.set_automatic_self_calibration(true).unwrap();
+        sensor.set_temperature_offset(TemperatureOffset::from_centikelvin(150)).unwrap();
+        sensor.set_altitude_compensation(Altitude::from_meters(520)).unwrap();
+
+        assert_eq!(sensor.get_measurement_interval().map(|interval| interval.seconds()), Ok(5));
+        assert_eq!(sensor.get_automatic_self_calibration(), Ok(true));
+        assert_eq!(sensor.get_temperature_offset(), Ok(TemperatureOffset::from_centikelvin(150)));
+        assert_eq!(sensor.get_altitude_compensation(), Ok(Altitude::from_meters(520)));
+        let version = sensor.get_firmware_version().unwrap();
+        assert_eq!((version.major, version.minor), FIRMWARE_VERSION);
+
+        // The temperature offset gets subtracted from the readings and new
+        // measurements only become ready after the configured interval.
+        let mut sensor = sensor.start_continuous_measurement(None).unwrap();
+        sim.advance_seconds(4);
+        assert_eq!(sensor.is_measurement_ready(), Ok(false));
+        sim.advance_seconds(1);
+        assert_eq!(sensor.get_measurement().map(|measurement| measurement.temperature_celsius), Ok(Some(21.0)));
+
+        sensor.set_forced_recalibration_value(450).unwrap();
+        assert_eq!(sim.state().frc_ppm, 450);
+    }
+
+
+    #[test]
+    fn injects_faults() {
+        let sim = sim();
+        let sensor = Scd30::new(sim.i2c(), NoopDelay::new());
+        let mut sensor = sensor.start_continuous_measurement(None).unwrap();
+        sim.advance_seconds(2);
+
+        sim.set_fault(Some(Fault::CorruptCrc));
+        assert!(matches!(sensor.get_measurement(), Err(Error::CrcError(CrcError{ word: 0, .. }))));
+
+        sim.set_fault(Some(Fault::Nack));
+        assert_eq!(sensor.is_measurement_ready(), Err(Error::I2cError(SimError::Nack)));
+
+        sim.set_fault(Some(Fault::StuckDataReady(false)));
+        sim.advance_seconds(2);
+        assert_eq!(sensor.is_measurement_ready(), Ok(false));
+
+        sim.set_fault(None);
+        assert_eq!(sensor.is_measurement_ready(), Ok(true));
+    }
+
+
+    #[cfg(feature = "eh0")]
+    #[test]
+    fn works_through_embedded_hal_0_2() {
+        use crate::compat::{Eh0I2c, Eh0I2cError};
+
+        let sim = sim();
+        let mut sensor = Scd30::new(Eh0I2c::new(sim.i2c()), NoopDelay::new());
+
+        assert_eq!(sensor.get_measurement_interval().map(|interval| interval.seconds()), Ok(2));
+        sim.set_fault(Some(Fault::Nack));
+        assert_eq!(sensor.soft_reset(), Err(Error::I2cError(Eh0I2cError(SimError::Nack))));
+    }
+}
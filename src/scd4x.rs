@@ -0,0 +1,643 @@
+// A driver for the Sensirion SCD40 and SCD41 CO2 sensors. They speak a
+// different command set than the SCD30 but use the same framing and CRC for
+// commands and responses.
+//
+// See 'Datasheet SCD4x' for the details.
+
+
+use core::fmt;
+use core::marker::PhantomData;
+use defmt::Format;
+use embedded_hal_1::delay::DelayNs;
+use embedded_hal_1::i2c::{self, ErrorKind, I2c};
+
+use crate::co2_sensor::{Co2Sensor, MeasuringCo2Sensor, SensorError};
+use crate::sensirion::{command_frame, command_with_argument_frame, read_words, CrcError, ReadError};
+use crate::scd30::{Altitude, AmbientPressure, Measurement, ModeChangeError};
+
+
+
+
+// A custom error type for reporting errors from both, the driver itself and
+// the underlying I2C implementation.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error<E> {
+    // The CRC of a word from a response did not match its content.
+    CrcError(CrcError),
+    // An argument outside the range accepted by the sensor got rejected
+    // without talking to it. Carries the command and the rejected argument.
+    InvalidArgument{ command: u16, argument: u16 },
+    // The sensor did not report a measurement as ready in time.
+    NotReady,
+    // The sensor could not perform the forced recalibration. This happens
+    // when it has not been measuring before.
+    RecalibrationFailed,
+    I2cError(E),
+}
+
+
+// Marker types for the sensor's measurement mode. Starting and stopping
+// periodic measurement consumes the driver and returns it in the new mode.
+// Commands which only make sense in one mode are only available there.
+pub struct Idle;
+// Measuring every 5 s.
+pub struct Periodic;
+// Measuring every 30 s for saving power.
+pub struct LowPowerPeriodic;
+
+
+pub struct Scd4x<I2C: I2c, D: DelayNs, MODE = Idle> {
+    i2c: I2C,
+    delay: D,
+    mode: PhantomData<MODE>,
+}
+
+
+
+
+// The periodic measurement modes. They only differ in the command starting
+// them.
+pub trait PeriodicMode {
+    const START_COMMAND: u16;
+}
+
+
+
+
+pub const I2C_ADDRESS: u8 = 0x62;
+
+// The highest ambient pressure accepted by the sensor. Other than the SCD30,
+// it does not take pressures up to 1400 hPa.
+pub const AMBIENT_PRESSURE_MAX_HPA: u16 = 1200;
+// The reference concentrations accepted for forced recalibration. This is the
+// sensor's specified CO2 measurement range.
+pub const FRC_MIN_PPM: u16 = 400;
+pub const FRC_MAX_PPM: u16 = 5000;
+
+
+// Command words from section 3.5 'Command Overview'.
+const CMD_START_PERIODIC_MEASUREMENT: u16 = 0x21b1;
+const CMD_READ_MEASUREMENT: u16 = 0xec05;
+const CMD_STOP_PERIODIC_MEASUREMENT: u16 = 0x3f86;
+const CMD_SET_SENSOR_ALTITUDE: u16 = 0x2427;
+const CMD_GET_SENSOR_ALTITUDE: u16 = 0x2322;
+const CMD_SET_AMBIENT_PRESSURE: u16 = 0xe000;
+const CMD_PERFORM_FORCED_RECALIBRATION: u16 = 0x362f;
+const CMD_SET_AUTOMATIC_SELF_CALIBRATION: u16 = 0x2416;
+const CMD_GET_AUTOMATIC_SELF_CALIBRATION: u16 = 0x2313;
+const CMD_START_LOW_POWER_PERIODIC_MEASUREMENT: u16 = 0x21ac;
+const CMD_GET_DATA_READY_STATUS: u16 = 0xe4b8;
+const CMD_PERSIST_SETTINGS: u16 = 0x3615;
+const CMD_GET_SERIAL_NUMBER: u16 = 0x3682;
+const CMD_REINIT: u16 = 0x3646;
+const CMD_MEASURE_SINGLE_SHOT: u16 = 0x219d;
+
+
+// Execution times of the commands. The sensor does not respond to other
+// commands until they have passed.
+const COMMAND_EXECUTION_MS: u32 = 1;
+const STOP_PERIODIC_MEASUREMENT_MS: u32 = 500;
+const FORCED_RECALIBRATION_MS: u32 = 400;
+const PERSIST_SETTINGS_MS: u32 = 800;
+const REINIT_MS: u32 = 20;
+const MEASURE_SINGLE_SHOT_MS: u32 = 5_000;
+
+// The response to a failed forced recalibration.
+const FRC_FAILED: u16 = 0xffff;
+// The forced recalibration correction is sent with this offset.
+const FRC_CORRECTION_OFFSET: i32 = 0x8000;
+// A measurement is ready if any of these bits of the data ready status is set.
+const DATA_READY_MASK: u16 = 0x07ff;
+
+
+
+
+impl PeriodicMode for Periodic {
+    const START_COMMAND: u16 = CMD_START_PERIODIC_MEASUREMENT;
+}
+
+
+impl PeriodicMode for LowPowerPeriodic {
+    const START_COMMAND: u16 = CMD_START_LOW_POWER_PERIODIC_MEASUREMENT;
+}
+
+
+// The I2C implementation's error gets formatted via its Debug implementation
+// as not all of them implement Format.
+impl<E: fmt::Debug> Format for Error<E> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::CrcError(error) => defmt::write!(f, "{}", error),
+            Error::InvalidArgument{ command, argument } =>
+                defmt::write!(f, "invalid argument {=u16} for command {=u16:#06x}", argument, command),
+            Error::NotReady => defmt::write!(f, "sensor not ready"),
+            Error::RecalibrationFailed => defmt::write!(f, "forced recalibration failed"),
+            Error::I2cError(error) => defmt::write!(f, "I2C error: {}", defmt::Debug2Format(error)),
+        }
+    }
+}
+
+
+impl<E: i2c::Error> SensorError for Error<E> {
+    // Whether retrying might succeed. See the implementation for scd30::Error.
+    fn is_transient(&self) -> bool {
+        match self {
+            Error::CrcError(_) => true,
+            Error::I2cError(error) => matches!(error.kind(), ErrorKind::NoAcknowledge(_)),
+            _ => false,
+        }
+    }
+
+
+    fn not_ready() -> Self {
+        Error::NotReady
+    }
+}
+
+
+// Allow automatic conversion from the I2C implementation's error type to the
+// driver's error type (for the question mark operator).
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::I2cError(err)
+    }
+}
+
+
+// Allow automatic conversion from errors reading words via
+// sensirion::read_words (for the question mark operator).
+impl<E> From<ReadError<E>> for Error<E> {
+    fn from(error: ReadError<E>) -> Error<E> {
+        match error {
+            ReadError::CrcError(error) => Error::CrcError(error),
+            ReadError::I2cError(error) => Error::I2cError(error),
+        }
+    }
+}
+
+
+// Starting measurement and changing the sensor's settings is only possible
+// while it is idle.
+impl<I2C, D, E> Scd4x<I2C, D, Idle> where I2C: I2c<Error = E>, D: DelayNs {
+    // The driver takes ownership of a delay for waiting for commands to
+    // complete.
+    pub fn new(i2c: I2C, delay: D) -> Self {
+        Scd4x{ i2c, delay, mode: PhantomData }
+    }
+
+
+    pub fn start_periodic_measurement(self) -> Result<Scd4x<I2C, D, Periodic>, ModeChangeError<Error<E>, Self>> {
+        self.start::<Periodic>()
+    }
+
+
+    pub fn start_low_power_periodic_measurement(self) -> Result<Scd4x<I2C, D, LowPowerPeriodic>, ModeChangeError<Error<E>, Self>> {
+        self.start::<LowPowerPeriodic>()
+    }
+
+
+    // Performs a single measurement on demand and returns it after about
+    // 5 s. This is only supported by the SCD41.
+    pub fn measure_single_shot(&mut self) -> Result<Measurement, Error<E>> {
+        self.write_command(CMD_MEASURE_SINGLE_SHOT, MEASURE_SINGLE_SHOT_MS)?;
+        self.read_measurement()
+    }
+
+
+    // Recalibrates the sensor against a known CO2 concentration and returns
+    // the resulting correction in ppm. The sensor should have been measuring
+    // in this environment for at least three minutes before. Values outside
+    // the measurement range get rejected without talking to the sensor.
+    pub fn perform_forced_recalibration(&mut self, co2_ppm: u16) -> Result<i16, Error<E>> {
+        check_forced_recalibration_value(co2_ppm)?;
+        self.forced_recalibration(co2_ppm)
+    }
+
+
+    // Turns automatic self-calibration (ASC) on or off. Like all settings,
+    // this is only kept across power cycles after persist_settings.
+    pub fn set_automatic_self_calibration(&mut self, active: bool) -> Result<(), Error<E>> {
+        self.write_command_with_argument(CMD_SET_AUTOMATIC_SELF_CALIBRATION, active as u16, COMMAND_EXECUTION_MS)
+    }
+
+
+    pub fn get_automatic_self_calibration(&mut self) -> Result<bool, Error<E>> {
+        self.write_command(CMD_GET_AUTOMATIC_SELF_CALIBRATION, COMMAND_EXECUTION_MS)?;
+        let [active] = read_words(&mut self.i2c, I2C_ADDRESS)?;
+        Ok(active == 1u16)
+    }
+
+
+    // Altitude compensation is overridden by setting an ambient pressure.
+    pub fn set_sensor_altitude(&mut self, altitude: Altitude) -> Result<(), Error<E>> {
+        self.write_command_with_argument(CMD_SET_SENSOR_ALTITUDE, altitude.meters(), COMMAND_EXECUTION_MS)
+    }
+
+
+    pub fn get_sensor_altitude(&mut self) -> Result<Altitude, Error<E>> {
+        self.write_command(CMD_GET_SENSOR_ALTITUDE, COMMAND_EXECUTION_MS)?;
+        let [meters] = read_words(&mut self.i2c, I2C_ADDRESS)?;
+        Ok(Altitude::from_meters(meters))
+    }
+
+
+    // Stores the current settings in the sensor's EEPROM. Its endurance is
+    // limited to about 2000 writes.
+    pub fn persist_settings(&mut self) -> Result<(), Error<E>> {
+        self.write_command(CMD_PERSIST_SETTINGS, PERSIST_SETTINGS_MS)
+    }
+
+
+    // Reloads the settings from the sensor's EEPROM.
+    pub fn reinit(&mut self) -> Result<(), Error<E>> {
+        self.write_command(CMD_REINIT, REINIT_MS)
+    }
+
+
+    pub fn get_serial_number(&mut self) -> Result<u64, Error<E>> {
+        self.write_command(CMD_GET_SERIAL_NUMBER, COMMAND_EXECUTION_MS)?;
+        let words: [u16; 3] = read_words(&mut self.i2c, I2C_ADDRESS)?;
+        Ok(words.iter().fold(0u64, |serial, word| serial << 16 | u64::from(*word)))
+    }
+
+
+    fn start<MODE: PeriodicMode>(self) -> Result<Scd4x<I2C, D, MODE>, ModeChangeError<Error<E>, Self>> {
+        let mut sensor = self;
+
+        match sensor.write_command(MODE::START_COMMAND, 0) {
+            Ok(()) => Ok(sensor.into_mode()),
+            Err(error) => Err(ModeChangeError{ error, sensor }),
+        }
+    }
+}
+
+
+// Reading measurements requires the sensor to measure periodically in either
+// of its modes.
+impl<I2C, D, E, MODE> Scd4x<I2C, D, MODE> where I2C: I2c<Error = E>, D: DelayNs, MODE: PeriodicMode {
+    pub fn is_measurement_ready(&mut self) -> Result<bool, Error<E>> {
+        self.write_command(CMD_GET_DATA_READY_STATUS, COMMAND_EXECUTION_MS)?;
+        let [status] = read_words(&mut self.i2c, I2C_ADDRESS)?;
+        Ok(status & DATA_READY_MASK != 0)
+    }
+
+
+    pub fn get_measurement(&mut self) -> Result<Measurement, Error<E>> {
+        self.read_measurement()
+    }
+
+
+
+    // The sensor only performs forced recalibration while idle. Measurement
+    // gets stopped for it and resumed afterwards, even if recalibration
+    // failed. If resuming fails, its error gets returned and the correction
+    // is lost although the sensor has applied it. Values outside the
+    // measurement range get rejected without talking to the sensor.
+    pub fn perform_forced_recalibration(&mut self, co2_ppm: u16) -> Result<i16, Error<E>> {
+        check_forced_recalibration_value(co2_ppm)?;
+        self.write_command(CMD_STOP_PERIODIC_MEASUREMENT, STOP_PERIODIC_MEASUREMENT_MS)?;
+        let correction = self.forced_recalibration(co2_ppm);
+        self.write_command(MODE::START_COMMAND, 0)?;
+
+        correction
+    }
+}
+
+
+// Setting the ambient pressure and stopping measurement is possible in any
+// mode.
+impl<I2C, D, E, MODE> Scd4x<I2C, D, MODE> where I2C: I2c<Error = E>, D: DelayNs {
+    // Gives back the I2C bus and the delay.
+    pub fn release(self) -> (I2C, D) {
+        (self.i2c, self.delay)
+    }
+
+
+    // Overrides altitude compensation. Pressures above 1200 hPa get rejected
+    // without talking to the sensor.
+    pub fn set_ambient_pressure(&mut self, pressure: AmbientPressure) -> Result<(), Error<E>> {
+        let hpa = pressure.mbar();
+
+        if hpa > AMBIENT_PRESSURE_MAX_HPA {
+            return Err(Error::InvalidArgument{ command: CMD_SET_AMBIENT_PRESSURE, argument: hpa });
+        }
+        self.write_command_with_argument(CMD_SET_AMBIENT_PRESSURE, hpa, COMMAND_EXECUTION_MS)
+    }
+
+
+    // Stopping periodic measurement is accepted by the sensor in any mode.
+    // This brings the driver in sync with a sensor which might still be
+    // measuring after a reset of the microcontroller.
+    pub fn stop_periodic_measurement(self) -> Result<Scd4x<I2C, D, Idle>, ModeChangeError<Error<E>, Self>> {
+        let mut sensor = self;
+
+        match sensor.write_command(CMD_STOP_PERIODIC_MEASUREMENT, STOP_PERIODIC_MEASUREMENT_MS) {
+            Ok(()) => Ok(sensor.into_mode()),
+            Err(error) => Err(ModeChangeError{ error, sensor }),
+        }
+    }
+
+
+    fn into_mode<NEW>(self) -> Scd4x<I2C, D, NEW> {
+        Scd4x{ i2c: self.i2c, delay: self.delay, mode: PhantomData }
+    }
+
+
+    fn read_measurement(&mut self) -> Result<Measurement, Error<E>> {
+        self.write_command(CMD_READ_MEASUREMENT, COMMAND_EXECUTION_MS)?;
+        let words = read_words(&mut self.i2c, I2C_ADDRESS)?;
+        Ok(measurement_from_words(words))
+    }
+
+
+    fn forced_recalibration(&mut self, co2_ppm: u16) -> Result<i16, Error<E>> {
+        self.write_command_with_argument(CMD_PERFORM_FORCED_RECALIBRATION, co2_ppm, FORCED_RECALIBRATION_MS)?;
+        let [correction] = read_words(&mut self.i2c, I2C_ADDRESS)?;
+
+        if correction == FRC_FAILED {
+            Err(Error::RecalibrationFailed)
+        } else {
+            Ok((i32::from(correction) - FRC_CORRECTION_OFFSET) as i16)
+        }
+    }
+
+
+    // Sends a command and waits for the sensor to execute it.
+    fn write_command(&mut self, command: u16, execution_ms: u32) -> Result<(), Error<E>> {
+        let frame = command_frame(command);
+        defmt::trace!("command: {=[u8]}", frame);
+
+        self.i2c.write(I2C_ADDRESS, &frame)?;
+        self.delay.delay_ms(execution_ms);
+        Ok(())
+    }
+
+
+    fn write_command_with_argument(&mut self, command: u16, argument: u16, execution_ms: u32) -> Result<(), Error<E>> {
+        let frame = command_with_argument_frame(command, argument);
+        defmt::trace!("command: {=[u8]}", frame);
+
+        self.i2c.write(I2C_ADDRESS, &frame)?;
+        self.delay.delay_ms(execution_ms);
+        Ok(())
+    }
+}
+
+
+impl<I2C, D, E> Co2Sensor for Scd4x<I2C, D, Idle> where I2C: I2c<Error = E>, D: DelayNs, E: i2c::Error {
+    type Error = Error<E>;
+    type Measuring = Scd4x<I2C, D, Periodic>;
+
+    fn start_measurement(self, pressure: Option<AmbientPressure>) -> Result<Self::Measuring, ModeChangeError<Self::Error, Self>> {
+        let mut sensor = self;
+
+        if let Some(pressure) = pressure {
+            if let Err(error) = sensor.set_ambient_pressure(pressure) {
+                return Err(ModeChangeError{ error, sensor });
+            }
+        }
+        sensor.start_periodic_measurement()
+    }
+}
+
+
+impl<I2C, D, E, MODE> MeasuringCo2Sensor for Scd4x<I2C, D, MODE> where I2C: I2c<Error = E>, D: DelayNs, E: i2c::Error, MODE: PeriodicMode {
+    type Error = Error<E>;
+
+    fn is_measurement_ready(&mut self) -> Result<bool, Self::Error> {
+        Scd4x::is_measurement_ready(self)
+    }
+
+
+    fn read_measurement(&mut self) -> Result<Measurement, Self::Error> {
+        Scd4x::read_measurement(self)
+    }
+
+
+    fn pause_ms(&mut self, ms: u32) {
+        self.delay.delay_ms(ms);
+    }
+
+
+    fn set_ambient_pressure(&mut self, pressure: AmbientPressure) -> Result<(), Self::Error> {
+        Scd4x::set_ambient_pressure(self, pressure)
+    }
+
+
+    fn set_forced_recalibration_value(&mut self, co2_ppm: u16) -> Result<(), Self::Error> {
+        self.perform_forced_recalibration(co2_ppm).map(|_| ())
+    }
+}
+
+
+
+
+// Converts the raw words from the sensor into physical units as described in
+// section 3.6.2 'read_measurement'.
+fn measurement_from_words([co2, temperature, humidity]: [u16; 3]) -> Measurement {
+    Measurement {
+        co2_ppm: co2 as f32,
+        temperature_celsius: Some(-45.0 + 175.0 * temperature as f32 / 65536.0),
+        humidity_percent: Some(100.0 * humidity as f32 / 65536.0),
+    }
+}
+
+
+fn check_forced_recalibration_value<E>(co2_ppm: u16) -> Result<(), Error<E>> {
+    if (FRC_MIN_PPM..=FRC_MAX_PPM).contains(&co2_ppm) {
+        Ok(())
+    } else {
+        Err(Error::InvalidArgument{ command: CMD_PERFORM_FORCED_RECALIBRATION, argument: co2_ppm })
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scd30::WaitOptions;
+    use crate::testing::RecordingDelay;
+    use embedded_hal_1::i2c::NoAcknowledgeSource;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+    use std::cell::Cell;
+
+
+    // Creates a driver for a sensor which is already measuring.
+    fn periodic<D: DelayNs>(i2c: Mock, delay: D) -> Scd4x<Mock, D, Periodic> {
+        Scd4x{ i2c, delay, mode: PhantomData }
+    }
+
+
+    // Example response from section 3.6.2 'read_measurement'.
+    fn measurement_transactions() -> [Transaction; 2] {
+        [
+            Transaction::write(I2C_ADDRESS, vec![0xec, 0x05]),
+            Transaction::read(I2C_ADDRESS, vec![0x01, 0xf4, 0x33, 0x66, 0x67, 0xa2, 0x5e, 0xb9, 0x3c]),
+        ]
+    }
+
+
+    #[test]
+    fn start_measurement_sets_pressure_first() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0xe0, 0x00, 0x03, 0xf5, 0xdb]),
+            Transaction::write(I2C_ADDRESS, vec![0x21, 0xb1]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let sensor = Scd4x::new(i2c.clone(), NoopDelay::new());
+
+        sensor.start_measurement(AmbientPressure::from_mbar(1013)).unwrap();
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn start_low_power_periodic_measurement() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x21, 0xac]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let sensor = Scd4x::new(i2c.clone(), NoopDelay::new());
+
+        sensor.start_low_power_periodic_measurement().unwrap();
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn read_measurement_converts_units() {
+        let mut i2c = Mock::new(&measurement_transactions());
+        let paused_ns = Cell::new(0);
+        let mut sensor = periodic(i2c.clone(), RecordingDelay(&paused_ns));
+
+        let measurement = sensor.get_measurement().unwrap();
+        assert_eq!(measurement.co2_ppm, 500.0);
+        assert!((measurement.temperature_celsius.unwrap() - 25.0).abs() < 0.01);
+        assert!((measurement.humidity_percent.unwrap() - 37.0).abs() < 0.01);
+        assert!(paused_ns.get() >= 1_000_000);
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn read_measurement_reports_crc_error() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0xec, 0x05]),
+            Transaction::read(I2C_ADDRESS, vec![0x01, 0xf4, 0x33, 0x66, 0x67, 0xa3, 0x5e, 0xb9, 0x3c]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = periodic(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.get_measurement(), Err(Error::CrcError(CrcError{ word: 1, received: 0xa3, expected: 0xa2 })));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn wait_for_measurement_checks_data_ready_bits() {
+        let mut expectations = vec![
+            Transaction::write(I2C_ADDRESS, vec![0xe4, 0xb8]),
+            Transaction::read(I2C_ADDRESS, vec![0x80, 0x00, 0xa2]),
+            Transaction::write(I2C_ADDRESS, vec![0xe4, 0xb8])
+                .with_error(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)),
+            Transaction::write(I2C_ADDRESS, vec![0xe4, 0xb8]),
+            Transaction::read(I2C_ADDRESS, vec![0x80, 0x06, 0x04]),
+        ];
+        expectations.extend(measurement_transactions());
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = periodic(i2c.clone(), NoopDelay::new());
+
+        let options = WaitOptions{ poll_period_ms: 500, timeout_ms: 5_000, retries: 1 };
+        let measurement = MeasuringCo2Sensor::wait_for_measurement(&mut sensor, &options).unwrap();
+        assert_eq!(measurement.co2_ppm, 500.0);
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn set_ambient_pressure_rejects_unsupported_pressure() {
+        let mut i2c = Mock::new(&[]);
+        let mut sensor = periodic(i2c.clone(), NoopDelay::new());
+
+        let pressure = AmbientPressure::from_mbar(1300).unwrap();
+        assert_eq!(
+            sensor.set_ambient_pressure(pressure),
+            Err(Error::InvalidArgument{ command: CMD_SET_AMBIENT_PRESSURE, argument: 1300 }));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn forced_recalibration_pauses_periodic_measurement() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x3f, 0x86]),
+            Transaction::write(I2C_ADDRESS, vec![0x36, 0x2f, 0x01, 0x90, 0x4c]),
+            Transaction::read(I2C_ADDRESS, vec![0x80, 0x05, 0x57]),
+            Transaction::write(I2C_ADDRESS, vec![0x21, 0xb1]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let paused_ns = Cell::new(0);
+        let mut sensor = periodic(i2c.clone(), RecordingDelay(&paused_ns));
+
+        assert_eq!(sensor.perform_forced_recalibration(400), Ok(5));
+        assert!(paused_ns.get() >= (500 + 400) * 1_000_000);
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn forced_recalibration_failure_resumes_measurement() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x3f, 0x86]),
+            Transaction::write(I2C_ADDRESS, vec![0x36, 0x2f, 0x01, 0x90, 0x4c]),
+            Transaction::read(I2C_ADDRESS, vec![0xff, 0xff, 0xac]),
+            Transaction::write(I2C_ADDRESS, vec![0x21, 0xb1]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = periodic(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.set_forced_recalibration_value(400), Err(Error::RecalibrationFailed));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn forced_recalibration_rejects_values_outside_measurement_range() {
+        let mut i2c = Mock::new(&[]);
+        let mut sensor = periodic(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.perform_forced_recalibration(399),
+            Err(Error::InvalidArgument{ command: CMD_PERFORM_FORCED_RECALIBRATION, argument: 399 }));
+        assert_eq!(sensor.perform_forced_recalibration(5001),
+            Err(Error::InvalidArgument{ command: CMD_PERFORM_FORCED_RECALIBRATION, argument: 5001 }));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn get_serial_number() {
+        // Example from section 3.9.2 'get_serial_number'.
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x36, 0x82]),
+            Transaction::read(I2C_ADDRESS, vec![0xf8, 0x96, 0x31, 0x9f, 0x07, 0xc2, 0x3b, 0xb3, 0xc5]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Scd4x::new(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.get_serial_number(), Ok(0xf896_9f07_3bb3));
+
+        i2c.done();
+    }
+}
@@ -0,0 +1,521 @@
+// A driver for the SenseAir S8 NDIR CO2 sensor talking Modbus RTU over its
+// UART. The sensor measures continuously from power-up and only reports CO2,
+// so its measurements come without temperature and humidity.
+//
+// See 'Modbus on Senseair S8' for the register map and 'Product Specification
+// Senseair S8' for the details.
+
+
+use core::fmt;
+use defmt::Format;
+use embedded_hal_1::delay::DelayNs;
+use embedded_io::{Read, ReadReady, Write};
+
+use crate::co2_sensor::{Co2Sensor, MeasuringCo2Sensor, SensorError};
+use crate::modbus::{self, ModbusRtu};
+use crate::scd30::{AmbientPressure, Measurement, ModeChangeError};
+
+
+
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error<E> {
+    // An argument outside the range accepted by the sensor got rejected
+    // without talking to it. Carries the register and the rejected argument.
+    InvalidArgument{ register: u16, argument: u16 },
+    // The sensor reported an error in its meter status.
+    SensorError(MeterStatus),
+    // The sensor did not acknowledge the background calibration.
+    CalibrationFailed,
+    // The sensor did not update its reading in time.
+    NotReady,
+    // Errors from Modbus communication like CRC errors or exception
+    // responses from the sensor.
+    ModbusError(modbus::Error<E>),
+}
+
+
+// The meter status from input register IR1. Flags get cleared by the sensor
+// once the condition is gone.
+#[derive(Clone, Copy, Debug, Eq, Format, PartialEq)]
+pub struct MeterStatus(u16);
+
+
+#[derive(Clone, Copy, Debug, Eq, Format, PartialEq)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+
+pub struct SenseairS8<UART: Read + ReadReady + Write, D: DelayNs> {
+    modbus: ModbusRtu<UART, D>,
+    // The time paused while waiting since the last reading.
+    paused_since_read_ms: u32,
+}
+
+
+
+
+// The sensor answers to this address regardless of its configured one. This
+// only works with a single device on the bus.
+pub const MODBUS_ADDRESS: u8 = 0xfe;
+pub const BAUD_RATE: u32 = 9_600;
+
+// The sensor updates its reading every 2 s.
+pub const MEASUREMENT_PERIOD_MS: u32 = 2_000;
+// Background calibration sets the current reading to this concentration.
+pub const BACKGROUND_CO2_PPM: u16 = 400;
+// The factory default period for automatic baseline correction (ABC).
+pub const ABC_PERIOD_DEFAULT_HOURS: u16 = 180;
+
+
+// Input and holding register addresses from the Modbus documentation. They
+// are zero-based: IR1 has address 0.
+const IR_METER_STATUS: u16 = 0x0000;
+const IR_FIRMWARE_VERSION: u16 = 0x001c;
+const IR_SENSOR_ID: u16 = 0x001d;
+const HR_ACKNOWLEDGEMENT: u16 = 0x0000;
+const HR_SPECIAL_COMMAND: u16 = 0x0001;
+const HR_ABC_PERIOD: u16 = 0x001f;
+
+// The meter status, alarm status, output status and CO2 registers follow
+// each other and get read together.
+const MEASUREMENT_REGISTERS: usize = 4;
+const MEASUREMENT_CO2_INDEX: usize = 3;
+
+const COMMAND_BACKGROUND_CALIBRATION: u16 = 0x7c06;
+const ACKNOWLEDGEMENT_BACKGROUND_CALIBRATION: u16 = 1 << 5;
+const BACKGROUND_CALIBRATION_MS: u32 = 2_000;
+
+// Bits of the meter status. The out of range flag only indicates that the
+// concentration exceeds the sensor's measurement range and the reading got
+// capped. This does not make it an error.
+const STATUS_OUT_OF_RANGE: u16 = 1 << 5;
+const STATUS_ERROR_MASK: u16 = 0x007f & !STATUS_OUT_OF_RANGE;
+
+
+
+
+// The UART's error gets formatted via its Debug implementation as not all of
+// them implement Format.
+impl<E: fmt::Debug> Format for Error<E> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::InvalidArgument{ register, argument } =>
+                defmt::write!(f, "invalid argument {=u16} for register {=u16:#06x}", argument, register),
+            Error::SensorError(status) => defmt::write!(f, "sensor error: {}", status),
+            Error::CalibrationFailed => defmt::write!(f, "background calibration failed"),
+            Error::NotReady => defmt::write!(f, "sensor not ready"),
+            Error::ModbusError(error) => defmt::write!(f, "Modbus error: {}", defmt::Debug2Format(error)),
+        }
+    }
+}
+
+
+// Allow automatic conversion from Modbus errors to the driver's error type
+// (for the question mark operator).
+impl<E> From<modbus::Error<E>> for Error<E> {
+    fn from(err: modbus::Error<E>) -> Error<E> {
+        Error::ModbusError(err)
+    }
+}
+
+
+impl<E: fmt::Debug> SensorError for Error<E> {
+    // Whether retrying might succeed. This is the case for frames garbled
+    // or lost on the line.
+    fn is_transient(&self) -> bool {
+        matches!(self, Error::ModbusError(modbus::Error::CrcError | modbus::Error::UnexpectedResponse | modbus::Error::Timeout))
+    }
+
+
+    fn not_ready() -> Self {
+        Error::NotReady
+    }
+}
+
+
+impl MeterStatus {
+    pub fn raw(&self) -> u16 {
+        self.0
+    }
+
+
+    pub fn is_error(&self) -> bool {
+        self.0 & STATUS_ERROR_MASK != 0
+    }
+
+
+    pub fn is_out_of_range(&self) -> bool {
+        self.0 & STATUS_OUT_OF_RANGE != 0
+    }
+}
+
+
+impl<UART, D, E> SenseairS8<UART, D> where UART: Read<Error = E> + ReadReady<Error = E> + Write<Error = E>, D: DelayNs {
+    // The UART needs to be configured for 9600 baud, 8 data bits, no parity
+    // and one stop bit. The driver takes ownership of a delay for keeping the
+    // silent interval between Modbus frames and waiting for measurements.
+    pub fn new(uart: UART, delay: D) -> Self {
+        SenseairS8{ modbus: ModbusRtu::new(uart, delay, BAUD_RATE), paused_since_read_ms: 0 }
+    }
+
+
+    // Gives back the UART and the delay.
+    pub fn release(self) -> (UART, D) {
+        self.modbus.release()
+    }
+
+
+    // Reads the latest CO2 concentration. Fails if the sensor reports an
+    // error in its meter status.
+    pub fn read_measurement(&mut self) -> Result<Measurement, Error<E>> {
+        let mut registers = [0u16; MEASUREMENT_REGISTERS];
+        self.modbus.read_input_registers(MODBUS_ADDRESS, IR_METER_STATUS, &mut registers)?;
+
+        let status = MeterStatus(registers[0]);
+        if status.is_error() {
+            return Err(Error::SensorError(status));
+        }
+        if status.is_out_of_range() {
+            defmt::debug!("CO2 out of measurement range");
+        }
+        self.paused_since_read_ms = 0;
+
+        Ok(Measurement {
+            co2_ppm: registers[MEASUREMENT_CO2_INDEX] as f32,
+            temperature_celsius: None,
+            humidity_percent: None,
+        })
+    }
+
+
+    pub fn read_meter_status(&mut self) -> Result<MeterStatus, Error<E>> {
+        let status = self.read_input_register(IR_METER_STATUS)?;
+        Ok(MeterStatus(status))
+    }
+
+
+    pub fn get_firmware_version(&mut self) -> Result<FirmwareVersion, Error<E>> {
+        let [major, minor] = self.read_input_register(IR_FIRMWARE_VERSION)?.to_be_bytes();
+        Ok(FirmwareVersion{ major, minor })
+    }
+
+
+    pub fn get_sensor_id(&mut self) -> Result<u32, Error<E>> {
+        let mut registers = [0u16; 2];
+        self.modbus.read_input_registers(MODBUS_ADDRESS, IR_SENSOR_ID, &mut registers)?;
+        Ok(u32::from(registers[0]) << 16 | u32::from(registers[1]))
+    }
+
+
+    // Turns automatic baseline correction (ABC) on with the factory default
+    // period or off.
+    pub fn set_automatic_baseline_correction(&mut self, active: bool) -> Result<(), Error<E>> {
+        let hours = if active { ABC_PERIOD_DEFAULT_HOURS } else { 0 };
+        self.set_abc_period_hours(hours)
+    }
+
+
+    pub fn get_automatic_baseline_correction(&mut self) -> Result<bool, Error<E>> {
+        let hours = self.get_abc_period_hours()?;
+        Ok(hours != 0)
+    }
+
+
+    // Sets the period in which ABC adjusts the sensor's baseline to the
+    // lowest concentration seen. Zero turns ABC off.
+    pub fn set_abc_period_hours(&mut self, hours: u16) -> Result<(), Error<E>> {
+        self.modbus.write_single_register(MODBUS_ADDRESS, HR_ABC_PERIOD, hours)?;
+        Ok(())
+    }
+
+
+    pub fn get_abc_period_hours(&mut self) -> Result<u16, Error<E>> {
+        let mut hours = [0u16; 1];
+        self.modbus.read_holding_registers(MODBUS_ADDRESS, HR_ABC_PERIOD, &mut hours)?;
+        Ok(hours[0])
+    }
+
+
+    // Calibrates the sensor to read BACKGROUND_CO2_PPM at its current
+    // location. It needs to be exposed to fresh outdoor air for this.
+    pub fn perform_background_calibration(&mut self) -> Result<(), Error<E>> {
+        self.modbus.write_single_register(MODBUS_ADDRESS, HR_ACKNOWLEDGEMENT, 0x0000)?;
+        self.modbus.write_single_register(MODBUS_ADDRESS, HR_SPECIAL_COMMAND, COMMAND_BACKGROUND_CALIBRATION)?;
+        self.modbus.delay().delay_ms(BACKGROUND_CALIBRATION_MS);
+
+        let mut acknowledgement = [0u16; 1];
+        self.modbus.read_holding_registers(MODBUS_ADDRESS, HR_ACKNOWLEDGEMENT, &mut acknowledgement)?;
+        if acknowledgement[0] & ACKNOWLEDGEMENT_BACKGROUND_CALIBRATION != 0 {
+            Ok(())
+        } else {
+            Err(Error::CalibrationFailed)
+        }
+    }
+
+
+    fn read_input_register(&mut self, address: u16) -> Result<u16, Error<E>> {
+        let mut value = [0u16; 1];
+        self.modbus.read_input_registers(MODBUS_ADDRESS, address, &mut value)?;
+        Ok(value[0])
+    }
+}
+
+
+// The sensor has no idle mode. Starting measurement hands back the driver
+// as is.
+impl<UART, D, E> Co2Sensor for SenseairS8<UART, D> where UART: Read<Error = E> + ReadReady<Error = E> + Write<Error = E>, D: DelayNs, E: fmt::Debug {
+    type Error = Error<E>;
+    type Measuring = Self;
+
+    fn start_measurement(self, _pressure: Option<AmbientPressure>) -> Result<Self::Measuring, ModeChangeError<Self::Error, Self>> {
+        Ok(self)
+    }
+}
+
+
+impl<UART, D, E> MeasuringCo2Sensor for SenseairS8<UART, D> where UART: Read<Error = E> + ReadReady<Error = E> + Write<Error = E>, D: DelayNs, E: fmt::Debug {
+    type Error = Error<E>;
+
+    // There is no data-ready status and the driver has no clock. So it only
+    // accounts for the time it paused itself: a new reading is considered
+    // ready once waiting has covered a measurement period since the last one.
+    fn is_measurement_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.paused_since_read_ms >= MEASUREMENT_PERIOD_MS)
+    }
+
+
+    fn read_measurement(&mut self) -> Result<Measurement, Self::Error> {
+        SenseairS8::read_measurement(self)
+    }
+
+
+    fn pause_ms(&mut self, ms: u32) {
+        self.modbus.delay().delay_ms(ms);
+        self.paused_since_read_ms = self.paused_since_read_ms.saturating_add(ms);
+    }
+
+
+    // The sensor has no pressure compensation. The pressure gets ignored.
+    fn set_ambient_pressure(&mut self, _pressure: AmbientPressure) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+
+    // The sensor only supports calibrating to the background concentration.
+    fn set_forced_recalibration_value(&mut self, co2_ppm: u16) -> Result<(), Self::Error> {
+        if co2_ppm != BACKGROUND_CO2_PPM {
+            return Err(Error::InvalidArgument{ register: HR_SPECIAL_COMMAND, argument: co2_ppm });
+        }
+        self.perform_background_calibration()
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modbus::testing::ScriptedPeer;
+    use crate::scd30::WaitOptions;
+    use crate::testing::RecordingDelay;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use std::cell::Cell;
+
+
+    const READ_MEASUREMENT_REQUEST: &[u8] = &[0xfe, 0x04, 0x00, 0x00, 0x00, 0x04, 0xe5, 0xc6];
+
+
+    #[test]
+    fn read_co2_matches_example() {
+        // 'Read CO2' example from the Modbus documentation.
+        let mut peer = ScriptedPeer::new(&[(
+            &[0xfe, 0x04, 0x00, 0x03, 0x00, 0x01, 0xd5, 0xc5],
+            &[0xfe, 0x04, 0x02, 0x01, 0x90, 0xac, 0xd8],
+        )]);
+
+        let mut sensor = SenseairS8::new(&mut peer, NoopDelay::new());
+        assert_eq!(sensor.read_input_register(0x0003), Ok(400));
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn read_measurement_without_temperature_and_humidity() {
+        let mut peer = ScriptedPeer::new(&[(
+            READ_MEASUREMENT_REQUEST,
+            &[0xfe, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x90, 0x16, 0xe6],
+        )]);
+
+        let mut sensor = SenseairS8::new(&mut peer, NoopDelay::new());
+        let measurement = sensor.read_measurement().unwrap();
+        assert_eq!(measurement, Measurement{ co2_ppm: 400.0, temperature_celsius: None, humidity_percent: None });
+        assert!(measurement.validate().is_ok());
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn read_measurement_checks_meter_status() {
+        let mut peer = ScriptedPeer::new(&[
+            // Fatal error.
+            (
+                READ_MEASUREMENT_REQUEST,
+                &[0xfe, 0x04, 0x08, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07, 0xda],
+            ),
+            // Out of range at 2100 ppm.
+            (
+                READ_MEASUREMENT_REQUEST,
+                &[0xfe, 0x04, 0x08, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x08, 0x34, 0x30, 0xcf],
+            ),
+        ]);
+
+        let mut sensor = SenseairS8::new(&mut peer, NoopDelay::new());
+        assert_eq!(sensor.read_measurement(), Err(Error::SensorError(MeterStatus(0x0001))));
+        assert_eq!(sensor.read_measurement().map(|measurement| measurement.co2_ppm), Ok(2100.0));
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn wait_for_measurement_retries_transient_errors() {
+        let mut peer = ScriptedPeer::new(&[
+            // Garbled CRC.
+            (
+                READ_MEASUREMENT_REQUEST,
+                &[0xfe, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x90, 0x16, 0xe7],
+            ),
+            (
+                READ_MEASUREMENT_REQUEST,
+                &[0xfe, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x90, 0x16, 0xe6],
+            ),
+        ]);
+        let paused_ns = Cell::new(0);
+        let options = WaitOptions{ poll_period_ms: 100, timeout_ms: 3_000, retries: 1 };
+
+        let mut sensor = SenseairS8::new(&mut peer, RecordingDelay(&paused_ns));
+        assert_eq!(sensor.wait_for_measurement(&options).map(|measurement| measurement.co2_ppm), Ok(400.0));
+        assert!(paused_ns.get() >= 2_100_000_000);
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn wait_for_measurement_times_out_within_measurement_period() {
+        let mut peer = ScriptedPeer::new(&[]);
+        let paused_ns = Cell::new(0);
+        let options = WaitOptions{ poll_period_ms: 500, timeout_ms: 1_000, retries: 0 };
+
+        let mut sensor = SenseairS8::new(&mut peer, RecordingDelay(&paused_ns));
+        assert_eq!(sensor.wait_for_measurement(&options), Err(Error::NotReady));
+        assert_eq!(paused_ns.get(), 1_000_000_000);
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn automatic_baseline_correction() {
+        let mut peer = ScriptedPeer::new(&[
+            (
+                &[0xfe, 0x03, 0x00, 0x1f, 0x00, 0x01, 0xa1, 0xc3],
+                &[0xfe, 0x03, 0x02, 0x00, 0xb4, 0xac, 0x27],
+            ),
+            (
+                &[0xfe, 0x06, 0x00, 0x1f, 0x00, 0x00, 0xac, 0x03],
+                &[0xfe, 0x06, 0x00, 0x1f, 0x00, 0x00, 0xac, 0x03],
+            ),
+            (
+                &[0xfe, 0x06, 0x00, 0x1f, 0x00, 0xb4, 0xac, 0x74],
+                &[0xfe, 0x06, 0x00, 0x1f, 0x00, 0xb4, 0xac, 0x74],
+            ),
+        ]);
+
+        let mut sensor = SenseairS8::new(&mut peer, NoopDelay::new());
+        assert_eq!(sensor.get_automatic_baseline_correction(), Ok(true));
+        sensor.set_automatic_baseline_correction(false).unwrap();
+        sensor.set_automatic_baseline_correction(true).unwrap();
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn background_calibration_checks_acknowledgement() {
+        // Background calibration example from the Modbus documentation.
+        let calibration: [(&[u8], &[u8]); 2] = [
+            (
+                &[0xfe, 0x06, 0x00, 0x00, 0x00, 0x00, 0x9d, 0xc5],
+                &[0xfe, 0x06, 0x00, 0x00, 0x00, 0x00, 0x9d, 0xc5],
+            ),
+            (
+                &[0xfe, 0x06, 0x00, 0x01, 0x7c, 0x06, 0x6c, 0xc7],
+                &[0xfe, 0x06, 0x00, 0x01, 0x7c, 0x06, 0x6c, 0xc7],
+            ),
+        ];
+        let read_acknowledgement: &[u8] = &[0xfe, 0x03, 0x00, 0x00, 0x00, 0x01, 0x90, 0x05];
+        let mut peer = ScriptedPeer::new(&[
+            calibration[0],
+            calibration[1],
+            (read_acknowledgement, &[0xfe, 0x03, 0x02, 0x00, 0x20, 0xad, 0x88]),
+            calibration[0],
+            calibration[1],
+            (read_acknowledgement, &[0xfe, 0x03, 0x02, 0x00, 0x00, 0xac, 0x50]),
+        ]);
+        let paused_ns = Cell::new(0);
+
+        let mut sensor = SenseairS8::new(&mut peer, RecordingDelay(&paused_ns));
+        assert_eq!(sensor.set_forced_recalibration_value(BACKGROUND_CO2_PPM), Ok(()));
+        assert!(paused_ns.get() >= 2_000_000_000);
+        assert_eq!(sensor.perform_background_calibration(), Err(Error::CalibrationFailed));
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn forced_recalibration_only_to_background() {
+        let mut peer = ScriptedPeer::new(&[]);
+
+        let mut sensor = SenseairS8::new(&mut peer, NoopDelay::new());
+        assert_eq!(sensor.set_forced_recalibration_value(800), Err(Error::InvalidArgument{ register: HR_SPECIAL_COMMAND, argument: 800 }));
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn get_sensor_id() {
+        let mut peer = ScriptedPeer::new(&[(
+            &[0xfe, 0x04, 0x00, 0x1d, 0x00, 0x02, 0xf5, 0xc2],
+            &[0xfe, 0x04, 0x04, 0x01, 0x23, 0x45, 0x67, 0x77, 0xc7],
+        )]);
+
+        let mut sensor = SenseairS8::new(&mut peer, NoopDelay::new());
+        assert_eq!(sensor.get_sensor_id(), Ok(0x0123_4567));
+
+        peer.done();
+    }
+
+
+    #[test]
+    fn reports_exception_response() {
+        // Illegal data address.
+        let mut peer = ScriptedPeer::new(&[(
+            READ_MEASUREMENT_REQUEST,
+            &[0xfe, 0x84, 0x02, 0xf2, 0xf1],
+        )]);
+
+        let mut sensor = SenseairS8::new(&mut peer, NoopDelay::new());
+        assert_eq!(sensor.read_measurement(), Err(Error::ModbusError(modbus::Error::Exception(0x02))));
+
+        peer.done();
+    }
+}
@@ -0,0 +1,246 @@
+// The framing shared by Sensirion's I2C sensors: data gets transferred as
+// big-endian 16 bit words, each followed by a CRC-8 over its two bytes.
+// Commands are 16 bit words without a CRC, optionally followed by argument
+// words with CRC. This is independent of any sensor's command set and does
+// not allocate.
+//
+// See section 1.1.3 'I2C Checksum calculation' from the 'Interface
+// Description Sensirion SCD30 Sensor Module' for the CRC parameters.
+
+
+use core::fmt;
+use crc_all::Crc;
+use defmt::Format;
+use embedded_hal_1::i2c::I2c;
+
+
+
+
+// The CRC of a word from a frame did not match its content. Carries the index
+// of the word within the frame as well as the received and the expected CRC.
+// The drivers' errors wrap it as their CrcError variant.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CrcError {
+    pub word: u8,
+    pub received: u8,
+    pub expected: u8,
+}
+
+
+// An error from reading words via I2C. The drivers convert it into their own
+// error type with the question mark operator.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReadError<E> {
+    CrcError(CrcError),
+    I2cError(E),
+}
+
+
+
+
+// A word takes two bytes followed by its CRC.
+pub const WORD_LEN: usize = 3;
+// A command word gets sent without a CRC.
+pub const COMMAND_LEN: usize = 2;
+// The most words read_words reads at once. This covers the longest response
+// of all drivers, the SPS30's measurement with ten floats.
+pub const MAX_READ_WORDS: usize = 20;
+
+
+
+
+impl Format for CrcError {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "CRC error in word {=u8}: received {=u8:#04x}, expected {=u8:#04x}", self.word, self.received, self.expected)
+    }
+}
+
+
+// The I2C implementation's error gets formatted via its Debug implementation
+// as not all of them implement Format.
+impl<E: fmt::Debug> Format for ReadError<E> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            ReadError::CrcError(error) => defmt::write!(f, "{}", error),
+            ReadError::I2cError(error) => defmt::write!(f, "I2C error: {}", defmt::Debug2Format(error)),
+        }
+    }
+}
+
+
+
+
+// The length of a frame with the given number of words. This allows for
+// declaring fixed-size buffers like [0u8; frame_len(6)].
+pub const fn frame_len(words: usize) -> usize {
+    words * WORD_LEN
+}
+
+
+// The length of a command frame with the given number of argument words.
+pub const fn command_frame_len(arguments: usize) -> usize {
+    COMMAND_LEN + frame_len(arguments)
+}
+
+
+fn new_crc() -> Crc<u8> {
+    Crc::<u8>::new(0x31, 8, 0xff, 0x00, false)
+}
+
+
+pub fn crc(data: &[u8]) -> u8 {
+    let mut crc = new_crc();
+    crc.update(data);
+    crc.finish()
+}
+
+
+// Encodes the words into the frame. Panics if the frame's length does not
+// match the number of words.
+pub fn encode_words<const N: usize>(words: &[u16; N], frame: &mut [u8]) {
+    assert_eq!(frame.len(), frame_len(N));
+
+    for (word, chunk) in words.iter().zip(frame.chunks_exact_mut(WORD_LEN)) {
+        let [high, low] = word.to_be_bytes();
+        chunk.copy_from_slice(&[high, low, crc(&[high, low])]);
+    }
+}
+
+
+pub fn command_frame(command: u16) -> [u8; COMMAND_LEN] {
+    command.to_be_bytes()
+}
+
+
+pub fn command_with_argument_frame(command: u16, argument: u16) -> [u8; command_frame_len(1)] {
+    let mut frame = [0u8; command_frame_len(1)];
+    encode_command(command, &[argument], &mut frame);
+    frame
+}
+
+
+// Encodes the command followed by its arguments into the frame. Panics if the
+// frame's length does not match the number of arguments.
+pub fn encode_command<const N: usize>(command: u16, arguments: &[u16; N], frame: &mut [u8]) {
+    assert_eq!(frame.len(), command_frame_len(N));
+
+    frame[..COMMAND_LEN].copy_from_slice(&command_frame(command));
+    encode_words(arguments, &mut frame[COMMAND_LEN..]);
+}
+
+
+// Decodes the words from the frame after checking their CRCs. Panics if the
+// frame's length does not match the number of words.
+pub fn decode_words<const N: usize>(frame: &[u8]) -> Result<[u16; N], CrcError> {
+    assert_eq!(frame.len(), frame_len(N));
+    let mut words = [0u16; N];
+
+    for (index, (word, chunk)) in words.iter_mut().zip(frame.chunks_exact(WORD_LEN)).enumerate() {
+        let (high, low, received) = (chunk[0], chunk[1], chunk[2]);
+        let expected = crc(&[high, low]);
+
+        if received != expected {
+            return Err(CrcError{ word: index as u8, received, expected });
+        }
+        *word = u16::from_be_bytes([high, low]);
+    }
+
+    Ok(words)
+}
+
+
+// Reads the response to the previous command from the sensor at the given
+// address and returns its words after checking their CRCs. Panics if more
+// than MAX_READ_WORDS words are requested.
+pub fn read_words<I2C: I2c, const N: usize>(i2c: &mut I2C, address: u8) -> Result<[u16; N], ReadError<I2C::Error>> {
+    assert!(N <= MAX_READ_WORDS);
+    let mut buffer = [0u8; frame_len(MAX_READ_WORDS)];
+    let response = &mut buffer[..frame_len(N)];
+
+    i2c.read(address, response).map_err(ReadError::I2cError)?;
+    defmt::trace!("response: {=[u8]}", response);
+
+    decode_words(response).map_err(ReadError::CrcError)
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+
+    #[test]
+    fn crc_matches_example() {
+        // Example from section 1.1.3 'I2C Checksum calculation'.
+        assert_eq!(crc(&[0xbe, 0xef]), 0x92);
+    }
+
+
+    #[test]
+    fn encode_words_appends_crcs() {
+        let mut frame = [0u8; frame_len(2)];
+
+        encode_words(&[0xbeef, 0x0001], &mut frame);
+
+        assert_eq!(frame, [0xbe, 0xef, 0x92, 0x00, 0x01, 0xb0]);
+    }
+
+
+    #[test]
+    fn command_frames() {
+        assert_eq!(command_frame(0xd304), [0xd3, 0x04]);
+        assert_eq!(command_with_argument_frame(0x4600, 2), [0x46, 0x00, 0x00, 0x02, 0xe3]);
+    }
+
+
+    #[test]
+    fn encode_command_appends_arguments() {
+        let mut frame = [0u8; command_frame_len(2)];
+
+        encode_command(0x260f, &[0x8000, 0x6666], &mut frame);
+
+        assert_eq!(frame, [0x26, 0x0f, 0x80, 0x00, 0xa2, 0x66, 0x66, 0x93]);
+    }
+
+
+    #[test]
+    fn decode_words_roundtrip() {
+        let mut frame = [0u8; frame_len(3)];
+        encode_words(&[0x01f4, 0x6667, 0x5eb9], &mut frame);
+
+        assert_eq!(decode_words(&frame), Ok([0x01f4, 0x6667, 0x5eb9]));
+    }
+
+
+    #[test]
+    fn decode_words_names_failing_word() {
+        let frame = [0xbe, 0xef, 0x92, 0x00, 0x01, 0xb1];
+
+        assert_eq!(decode_words::<2>(&frame), Err(CrcError{ word: 1, received: 0xb1, expected: 0xb0 }));
+    }
+
+
+    #[test]
+    fn read_words_checks_crcs() {
+        let mut i2c = Mock::new(&[
+            Transaction::read(0x62, vec![0xbe, 0xef, 0x92, 0x00, 0x01, 0xb0]),
+            Transaction::read(0x62, vec![0xbe, 0xef, 0x92, 0x00, 0x01, 0xb1]),
+        ]);
+
+        assert_eq!(read_words(&mut i2c, 0x62), Ok([0xbeef, 0x0001]));
+        assert_eq!(read_words::<_, 2>(&mut i2c, 0x62),
+            Err(ReadError::CrcError(CrcError{ word: 1, received: 0xb1, expected: 0xb0 })));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn decode_words_rejects_frame_length_mismatch() {
+        let _ = decode_words::<2>(&[0xbe, 0xef, 0x92]);
+    }
+}
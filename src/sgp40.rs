@@ -0,0 +1,302 @@
+// A driver for the Sensirion SGP40 VOC sensor. It complements the CO2 sensors
+// by picking up pollution like cooking fumes or cleaning products. The sensor
+// only provides a raw signal which needs to be fed into the VOC index
+// algorithm from voc_index at a fixed interval.
+//
+// The raw signal depends on temperature and humidity. Each measurement takes
+// them as compensation, for example from the latest SCD30 measurement.
+//
+// See 'Datasheet SGP40' for the details.
+
+
+use core::fmt;
+use defmt::Format;
+use embedded_hal_1::delay::DelayNs;
+use embedded_hal_1::i2c::I2c;
+
+use crate::scd30;
+use crate::sensirion::{command_frame, command_frame_len, encode_command, read_words, CrcError, ReadError};
+
+
+pub mod voc_index;
+
+
+
+
+// A custom error type for reporting errors from both, the driver itself and
+// the underlying I2C implementation.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error<E> {
+    // The CRC of a word from a response did not match its content.
+    CrcError(CrcError),
+    // The sensor reported a failing self-test. Carries the result it
+    // returned.
+    SelfTestFailed(u16),
+    I2cError(E),
+}
+
+
+// Temperature and humidity of the air the sensor is measuring.
+#[derive(Clone, Copy, Debug, Format, PartialEq)]
+pub struct Compensation {
+    pub temperature_celsius: f32,
+    pub humidity_percent: f32,
+}
+
+
+pub struct Sgp40<I2C: I2c, D: DelayNs> {
+    i2c: I2C,
+    delay: D,
+}
+
+
+
+
+pub const I2C_ADDRESS: u8 = 0x59;
+
+
+// Commands from section 4.4 'I2C commands'.
+const CMD_MEASURE_RAW_SIGNAL: u16 = 0x260f;
+const CMD_EXECUTE_SELF_TEST: u16 = 0x280e;
+const CMD_TURN_HEATER_OFF: u16 = 0x3615;
+const CMD_GET_SERIAL_NUMBER: u16 = 0x3682;
+
+// Maximum execution times from section 4.5 'I2C Commands'.
+const MEASURE_RAW_SIGNAL_MS: u32 = 30;
+const EXECUTE_SELF_TEST_MS: u32 = 320;
+const COMMAND_EXECUTION_MS: u32 = 1;
+
+const SELF_TEST_PASSED: u16 = 0xd400;
+
+
+
+
+// The I2C implementation's error gets formatted via its Debug implementation
+// as not all of them implement Format.
+impl<E: fmt::Debug> Format for Error<E> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::CrcError(error) => defmt::write!(f, "{}", error),
+            Error::SelfTestFailed(result) => defmt::write!(f, "self-test failed: {=u16:#06x}", result),
+            Error::I2cError(error) => defmt::write!(f, "I2C error: {}", defmt::Debug2Format(error)),
+        }
+    }
+}
+
+
+// Allow automatic conversion from the I2C implementation's error type to the
+// driver's error type (for the question mark operator).
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::I2cError(err)
+    }
+}
+
+
+// Allow automatic conversion from errors reading words via
+// sensirion::read_words (for the question mark operator).
+impl<E> From<ReadError<E>> for Error<E> {
+    fn from(error: ReadError<E>) -> Error<E> {
+        match error {
+            ReadError::CrcError(error) => Error::CrcError(error),
+            ReadError::I2cError(error) => Error::I2cError(error),
+        }
+    }
+}
+
+
+impl Compensation {
+    // Converts to the sensor's ticks as described in table 10 'Description
+    // of the I2C measurement command'. Values outside the sensor's range get
+    // cropped.
+    fn ticks(&self) -> [u16; 2] {
+        let humidity = self.humidity_percent.clamp(0.0, 100.0);
+        let temperature = self.temperature_celsius.clamp(-45.0, 130.0);
+
+        [
+            (humidity * 65535.0 / 100.0 + 0.5) as u16,
+            ((temperature + 45.0) * 65535.0 / 175.0 + 0.5) as u16,
+        ]
+    }
+}
+
+
+// The sensor's defaults for measuring without compensation.
+impl Default for Compensation {
+    fn default() -> Self {
+        Compensation{ temperature_celsius: 25.0, humidity_percent: 50.0 }
+    }
+}
+
+
+// Falls back to the defaults for values absent from the measurement.
+impl From<&scd30::Measurement> for Compensation {
+    fn from(measurement: &scd30::Measurement) -> Self {
+        let default = Compensation::default();
+
+        Compensation {
+            temperature_celsius: measurement.temperature_celsius.unwrap_or(default.temperature_celsius),
+            humidity_percent: measurement.humidity_percent.unwrap_or(default.humidity_percent),
+        }
+    }
+}
+
+
+impl<I2C, D, E> Sgp40<I2C, D> where I2C: I2c<Error = E>, D: DelayNs {
+    // The driver takes ownership of a delay for waiting for commands to
+    // complete.
+    pub fn new(i2c: I2C, delay: D) -> Self {
+        Sgp40{ i2c, delay }
+    }
+
+
+    // Gives back the I2C bus and the delay.
+    pub fn release(self) -> (I2C, D) {
+        (self.i2c, self.delay)
+    }
+
+
+    // Measures the raw signal to feed into voc_index::VocIndexAlgorithm. The
+    // sensor heats up for the measurement and keeps its hotplate on
+    // afterwards.
+    pub fn measure_raw_signal(&mut self, compensation: &Compensation) -> Result<u16, Error<E>> {
+        let mut frame = [0u8; command_frame_len(2)];
+        encode_command(CMD_MEASURE_RAW_SIGNAL, &compensation.ticks(), &mut frame);
+        defmt::trace!("command: {=[u8]}", frame);
+
+        self.i2c.write(I2C_ADDRESS, &frame)?;
+        self.delay.delay_ms(MEASURE_RAW_SIGNAL_MS);
+        let [sraw] = read_words(&mut self.i2c, I2C_ADDRESS)?;
+        Ok(sraw)
+    }
+
+
+    // Tests the sensor's hotplate and returns an error if the test fails.
+    pub fn execute_self_test(&mut self) -> Result<(), Error<E>> {
+        self.write_command(CMD_EXECUTE_SELF_TEST, EXECUTE_SELF_TEST_MS)?;
+        let [result] = read_words(&mut self.i2c, I2C_ADDRESS)?;
+
+        if result == SELF_TEST_PASSED {
+            Ok(())
+        } else {
+            Err(Error::SelfTestFailed(result))
+        }
+    }
+
+
+    // Turns off the hotplate and puts the sensor into idle mode until the
+    // next measurement.
+    pub fn turn_heater_off(&mut self) -> Result<(), Error<E>> {
+        self.write_command(CMD_TURN_HEATER_OFF, COMMAND_EXECUTION_MS)
+    }
+
+
+    pub fn get_serial_number(&mut self) -> Result<u64, Error<E>> {
+        self.write_command(CMD_GET_SERIAL_NUMBER, COMMAND_EXECUTION_MS)?;
+        let words: [u16; 3] = read_words(&mut self.i2c, I2C_ADDRESS)?;
+        Ok(words.iter().fold(0, |serial, word| serial << 16 | u64::from(*word)))
+    }
+
+
+    fn write_command(&mut self, command: u16, execution_ms: u32) -> Result<(), Error<E>> {
+        let frame = command_frame(command);
+        defmt::trace!("command: {=[u8]}", frame);
+
+        self.i2c.write(I2C_ADDRESS, &frame)?;
+        self.delay.delay_ms(execution_ms);
+        Ok(())
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RecordingDelay;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+    use std::cell::Cell;
+
+
+    #[test]
+    fn measure_raw_signal_with_default_compensation() {
+        // Example from table 11 'Example of the I2C measurement command'.
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x26, 0x0f, 0x80, 0x00, 0xa2, 0x66, 0x66, 0x93]),
+            Transaction::read(I2C_ADDRESS, vec![0x66, 0x66, 0x93]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let paused_ns = Cell::new(0);
+        let mut sensor = Sgp40::new(i2c.clone(), RecordingDelay(&paused_ns));
+
+        assert_eq!(sensor.measure_raw_signal(&Compensation::default()), Ok(0x6666));
+        assert!(paused_ns.get() >= 30_000_000);
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn measure_raw_signal_compensates_with_scd30_measurement() {
+        let measurement = scd30::Measurement{ co2_ppm: 600.0, temperature_celsius: Some(25.0), humidity_percent: Some(100.0) };
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x26, 0x0f, 0xff, 0xff, 0xac, 0x66, 0x66, 0x93]),
+            Transaction::read(I2C_ADDRESS, vec![0x80, 0x00, 0xa2]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Sgp40::new(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.measure_raw_signal(&Compensation::from(&measurement)), Ok(0x8000));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn compensation_defaults_for_absent_values() {
+        let measurement = scd30::Measurement{ co2_ppm: 600.0, temperature_celsius: None, humidity_percent: Some(30.0) };
+        assert_eq!(Compensation::from(&measurement), Compensation{ temperature_celsius: 25.0, humidity_percent: 30.0 });
+    }
+
+
+    #[test]
+    fn compensation_gets_cropped() {
+        let compensation = Compensation{ temperature_celsius: -60.0, humidity_percent: 120.0 };
+        assert_eq!(compensation.ticks(), [0xffff, 0x0000]);
+    }
+
+
+    #[test]
+    fn execute_self_test_reports_failure() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x28, 0x0e]),
+            Transaction::read(I2C_ADDRESS, vec![0xd4, 0x00, 0xc6]),
+            Transaction::write(I2C_ADDRESS, vec![0x28, 0x0e]),
+            Transaction::read(I2C_ADDRESS, vec![0x4b, 0x00, 0x12]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Sgp40::new(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.execute_self_test(), Ok(()));
+        assert_eq!(sensor.execute_self_test(), Err(Error::SelfTestFailed(0x4b00)));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn get_serial_number() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x36, 0x82]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x00, 0x81, 0x12, 0x34, 0x37, 0x56, 0x78, 0x7d]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Sgp40::new(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.get_serial_number(), Ok(0x1234_5678));
+
+        i2c.done();
+    }
+}
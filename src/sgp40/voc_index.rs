@@ -0,0 +1,439 @@
+// A port of Sensirion's gas index algorithm for turning the SGP40's raw signal
+// into a VOC index. The index is relative to the sensor's recent history: 100
+// denotes the average conditions of the last hours, values up to 500 mean
+// more and values down to 1 mean fewer VOCs than usual.
+//
+// The algorithm learns this average by estimating mean and variance of the
+// raw signal. It learns quickly right after starting and settles down to
+// time constants of several hours later on. Learning gets gated while the
+// index indicates an event so that cooking for an hour does not become the
+// new normal.
+//
+// This follows the floating point variant of 'Sensirion Gas Index Algorithm'
+// version 3 (VOC part only). It expects to be fed a raw signal at a fixed
+// interval and does not allocate.
+
+
+use libm::{expf, sqrtf};
+
+
+
+
+// The first samples after starting get ignored while the sensor's hotplate
+// settles.
+const INITIAL_BLACKOUT_S: f32 = 45.0;
+const INDEX_GAIN: f32 = 230.0;
+const SRAW_STD_INITIAL: f32 = 50.0;
+const SRAW_STD_BONUS: f32 = 220.0;
+
+// Learning time constants for mean and variance of the raw signal.
+const TAU_MEAN_HOURS: f32 = 12.0;
+const TAU_VARIANCE_HOURS: f32 = 12.0;
+const TAU_INITIAL_MEAN_S: f32 = 20.0;
+const INIT_DURATION_MEAN_S: f32 = 3600.0 * 0.75;
+const INIT_TRANSITION_MEAN: f32 = 0.01;
+const TAU_INITIAL_VARIANCE_S: f32 = 2500.0;
+const INIT_DURATION_VARIANCE_S: f32 = 3600.0 * 1.45;
+const INIT_TRANSITION_VARIANCE: f32 = 0.01;
+
+// Gating of learning during events.
+const GATING_THRESHOLD: f32 = 340.0;
+const GATING_THRESHOLD_INITIAL: f32 = 510.0;
+const GATING_THRESHOLD_TRANSITION: f32 = 0.09;
+const GATING_MAX_DURATION_MINUTES: f32 = 60.0 * 3.0;
+const GATING_MAX_RATIO: f32 = 0.3;
+
+// Mapping to the index range.
+const SIGMOID_L: f32 = 500.0;
+const SIGMOID_K: f32 = -0.0065;
+const SIGMOID_X0: f32 = 213.0;
+const INDEX_OFFSET: f32 = 100.0;
+
+// Adaptive lowpass smoothing the index.
+const LP_TAU_FAST_S: f32 = 20.0;
+const LP_TAU_SLOW_S: f32 = 500.0;
+const LP_ALPHA: f32 = -0.2;
+
+const SRAW_MINIMUM: i32 = 20_000;
+const SRAW_RANGE: i32 = 32_767;
+const SRAW_INVALID: u16 = 65_000;
+const GAMMA_SCALING: f32 = 64.0;
+const ADDITIONAL_GAMMA_MEAN_SCALING: f32 = 8.0;
+// The C implementation's fixed point heritage limits the uptime counters to
+// this value.
+const UPTIME_LIMIT_S: f32 = 32_767.0;
+
+
+
+
+pub struct VocIndexAlgorithm {
+    sampling_interval_s: f32,
+    uptime_s: f32,
+    sraw: f32,
+    gas_index: f32,
+    estimator: MeanVarianceEstimator,
+    lowpass: AdaptiveLowpass,
+}
+
+
+// Tracks mean and standard deviation of the raw signal (offset by
+// SRAW_MINIMUM).
+struct MeanVarianceEstimator {
+    initialized: bool,
+    mean: f32,
+    sraw_offset: f32,
+    std: f32,
+    gamma_mean_learned: f32,
+    gamma_variance_learned: f32,
+    gamma_initial_mean: f32,
+    gamma_initial_variance: f32,
+    gamma_mean: f32,
+    gamma_variance: f32,
+    uptime_gamma_s: f32,
+    uptime_gating_s: f32,
+    gating_duration_minutes: f32,
+}
+
+
+struct AdaptiveLowpass {
+    a1: f32,
+    a2: f32,
+    state: Option<(f32, f32, f32)>,
+}
+
+
+
+
+impl VocIndexAlgorithm {
+    // Creates the algorithm for sampling every second, as recommended for
+    // the SGP40.
+    pub fn new() -> Self {
+        Self::with_sampling_interval(1.0)
+    }
+
+
+    // Creates the algorithm for the given sampling interval in seconds. The
+    // algorithm is tuned for one second and gets less responsive with longer
+    // intervals.
+    pub fn with_sampling_interval(sampling_interval_s: f32) -> Self {
+        VocIndexAlgorithm {
+            sampling_interval_s,
+            uptime_s: 0.0,
+            sraw: 0.0,
+            gas_index: 0.0,
+            estimator: MeanVarianceEstimator::new(sampling_interval_s),
+            lowpass: AdaptiveLowpass::new(sampling_interval_s),
+        }
+    }
+
+
+    pub fn sampling_interval_s(&self) -> f32 {
+        self.sampling_interval_s
+    }
+
+
+    // Forgets everything learned so far. The algorithm starts over with its
+    // initial blackout.
+    pub fn reset(&mut self) {
+        *self = Self::with_sampling_interval(self.sampling_interval_s);
+    }
+
+
+    // Processes the next raw signal from the sensor and returns the current
+    // VOC index. The index is 0 during the initial blackout and ranges from 1
+    // to 500 afterwards. Invalid raw signals (0 and from 65000 on) keep the
+    // index going with the last valid signal.
+    pub fn process(&mut self, sraw: u16) -> u16 {
+        if self.uptime_s <= INITIAL_BLACKOUT_S {
+            self.uptime_s += self.sampling_interval_s;
+        } else {
+            if sraw > 0 && sraw < SRAW_INVALID {
+                let sraw = i32::from(sraw).clamp(SRAW_MINIMUM + 1, SRAW_MINIMUM + SRAW_RANGE);
+                self.sraw = (sraw - SRAW_MINIMUM) as f32;
+            }
+
+            let index = self.estimator.mox_model(self.sraw);
+            let index = sigmoid_scaled(index);
+            let index = self.lowpass.process(index, self.sampling_interval_s);
+            self.gas_index = index.max(0.5);
+
+            if self.sraw > 0.0 {
+                self.estimator.process(self.sraw, self.gas_index, self.sampling_interval_s);
+            }
+        }
+
+        (self.gas_index + 0.5) as u16
+    }
+}
+
+
+impl Default for VocIndexAlgorithm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl MeanVarianceEstimator {
+    fn new(sampling_interval_s: f32) -> Self {
+        let interval_hours = sampling_interval_s / 3600.0;
+
+        MeanVarianceEstimator {
+            initialized: false,
+            mean: 0.0,
+            sraw_offset: 0.0,
+            std: SRAW_STD_INITIAL,
+            gamma_mean_learned: ADDITIONAL_GAMMA_MEAN_SCALING * GAMMA_SCALING * interval_hours
+                / (TAU_MEAN_HOURS + interval_hours),
+            gamma_variance_learned: GAMMA_SCALING * interval_hours / (TAU_VARIANCE_HOURS + interval_hours),
+            gamma_initial_mean: ADDITIONAL_GAMMA_MEAN_SCALING * GAMMA_SCALING * sampling_interval_s
+                / (TAU_INITIAL_MEAN_S + sampling_interval_s),
+            gamma_initial_variance: GAMMA_SCALING * sampling_interval_s / (TAU_INITIAL_VARIANCE_S + sampling_interval_s),
+            gamma_mean: 0.0,
+            gamma_variance: 0.0,
+            uptime_gamma_s: 0.0,
+            uptime_gating_s: 0.0,
+            gating_duration_minutes: 0.0,
+        }
+    }
+
+
+    fn mean(&self) -> f32 {
+        self.mean + self.sraw_offset
+    }
+
+
+    // Maps the raw signal to the index scale based on what has been learned
+    // so far. More VOCs lower the raw signal.
+    fn mox_model(&self, sraw: f32) -> f32 {
+        (sraw - self.mean()) / (-(self.std + SRAW_STD_BONUS)) * INDEX_GAIN
+    }
+
+
+    fn process(&mut self, sraw: f32, gas_index: f32, sampling_interval_s: f32) {
+        if !self.initialized {
+            self.initialized = true;
+            self.sraw_offset = sraw;
+            self.mean = 0.0;
+            return;
+        }
+
+        // Keep the mean small for retaining precision.
+        if self.mean >= 100.0 || self.mean <= -100.0 {
+            self.sraw_offset += self.mean;
+            self.mean = 0.0;
+        }
+
+        let sraw = sraw - self.sraw_offset;
+        self.update_gammas(gas_index, sampling_interval_s);
+
+        let delta = (sraw - self.mean) / GAMMA_SCALING;
+        let c = if delta < 0.0 { self.std - delta } else { self.std + delta };
+        let additional_scaling = if c > 1440.0 { (c / 1440.0) * (c / 1440.0) } else { 1.0 };
+
+        self.std = sqrtf(additional_scaling * (GAMMA_SCALING - self.gamma_variance))
+            * sqrtf(self.std * (self.std / (GAMMA_SCALING * additional_scaling))
+                + self.gamma_variance * delta / additional_scaling * delta);
+        self.mean += self.gamma_mean * delta / ADDITIONAL_GAMMA_MEAN_SCALING;
+    }
+
+
+    // Determines how much the current sample contributes to mean and
+    // variance. This blends from the initial to the learned time constants
+    // during the first hours and stops learning while the index indicates an
+    // event. Gating gets released if an event seems to last forever.
+    fn update_gammas(&mut self, gas_index: f32, sampling_interval_s: f32) {
+        let uptime_limit_s = UPTIME_LIMIT_S - sampling_interval_s;
+        if self.uptime_gamma_s < uptime_limit_s {
+            self.uptime_gamma_s += sampling_interval_s;
+        }
+        if self.uptime_gating_s < uptime_limit_s {
+            self.uptime_gating_s += sampling_interval_s;
+        }
+
+        let sigmoid_gamma_mean = sigmoid(self.uptime_gamma_s, INIT_DURATION_MEAN_S, INIT_TRANSITION_MEAN);
+        let gamma_mean = self.gamma_mean_learned
+            + (self.gamma_initial_mean - self.gamma_mean_learned) * sigmoid_gamma_mean;
+        let gating_threshold_mean = GATING_THRESHOLD
+            + (GATING_THRESHOLD_INITIAL - GATING_THRESHOLD)
+            * sigmoid(self.uptime_gating_s, INIT_DURATION_MEAN_S, INIT_TRANSITION_MEAN);
+        let sigmoid_gating_mean = sigmoid(gas_index, gating_threshold_mean, GATING_THRESHOLD_TRANSITION);
+        self.gamma_mean = sigmoid_gating_mean * gamma_mean;
+
+        let sigmoid_gamma_variance = sigmoid(self.uptime_gamma_s, INIT_DURATION_VARIANCE_S, INIT_TRANSITION_VARIANCE);
+        let gamma_variance = self.gamma_variance_learned
+            + (self.gamma_initial_variance - self.gamma_variance_learned)
+            * (sigmoid_gamma_variance - sigmoid_gamma_mean);
+        let gating_threshold_variance = GATING_THRESHOLD
+            + (GATING_THRESHOLD_INITIAL - GATING_THRESHOLD)
+            * sigmoid(self.uptime_gating_s, INIT_DURATION_VARIANCE_S, INIT_TRANSITION_VARIANCE);
+        let sigmoid_gating_variance = sigmoid(gas_index, gating_threshold_variance, GATING_THRESHOLD_TRANSITION);
+        self.gamma_variance = sigmoid_gating_variance * gamma_variance;
+
+        self.gating_duration_minutes += sampling_interval_s / 60.0
+            * ((1.0 - sigmoid_gating_mean) * (1.0 + GATING_MAX_RATIO) - GATING_MAX_RATIO);
+        if self.gating_duration_minutes < 0.0 {
+            self.gating_duration_minutes = 0.0;
+        }
+        if self.gating_duration_minutes > GATING_MAX_DURATION_MINUTES {
+            self.uptime_gating_s = 0.0;
+        }
+    }
+}
+
+
+impl AdaptiveLowpass {
+    fn new(sampling_interval_s: f32) -> Self {
+        AdaptiveLowpass {
+            a1: sampling_interval_s / (LP_TAU_FAST_S + sampling_interval_s),
+            a2: sampling_interval_s / (LP_TAU_SLOW_S + sampling_interval_s),
+            state: None,
+        }
+    }
+
+
+    // Smoothes the index with a time constant that shrinks as a fast and a
+    // slow lowpass diverge. This keeps the index steady while still following
+    // events quickly.
+    fn process(&mut self, sample: f32, sampling_interval_s: f32) -> f32 {
+        let (x1, x2, x3) = self.state.unwrap_or((sample, sample, sample));
+
+        let x1 = (1.0 - self.a1) * x1 + self.a1 * sample;
+        let x2 = (1.0 - self.a2) * x2 + self.a2 * sample;
+
+        let f1 = expf(LP_ALPHA * (x1 - x2).abs());
+        let tau_a = (LP_TAU_SLOW_S - LP_TAU_FAST_S) * f1 + LP_TAU_FAST_S;
+        let a3 = sampling_interval_s / (sampling_interval_s + tau_a);
+        let x3 = (1.0 - a3) * x3 + a3 * sample;
+
+        self.state = Some((x1, x2, x3));
+        x3
+    }
+}
+
+
+
+
+// A falling sigmoid from 1 to 0 around x0 for positive k.
+fn sigmoid(x: f32, x0: f32, k: f32) -> f32 {
+    let x = k * (x - x0);
+
+    if x < -50.0 {
+        1.0
+    } else if x > 50.0 {
+        0.0
+    } else {
+        1.0 / (1.0 + expf(x))
+    }
+}
+
+
+// Maps the output of the mox model to the index range with INDEX_OFFSET
+// representing average conditions.
+fn sigmoid_scaled(sample: f32) -> f32 {
+    let x = SIGMOID_K * (sample - SIGMOID_X0);
+
+    if x < -50.0 {
+        SIGMOID_L
+    } else if x > 50.0 {
+        0.0
+    } else if sample >= 0.0 {
+        let shift = (SIGMOID_L - 5.0 * INDEX_OFFSET) / 4.0;
+        (SIGMOID_L + shift) / (1.0 + expf(x)) - shift
+    } else {
+        SIGMOID_L / (1.0 + expf(x))
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    // A raw signal with some noise around a baseline, a VOC event lowering
+    // the signal for five minutes and a cleaner period raising it for
+    // another minute and a half.
+    fn raw_signal(second: u32) -> u16 {
+        let mut sraw = 30_000 + (second * 37) % 201 - 100;
+
+        if (4_000..4_300).contains(&second) {
+            sraw -= 2_500;
+        }
+        if (6_000..6_100).contains(&second) {
+            sraw += 1_500;
+        }
+
+        sraw as u16
+    }
+
+
+    #[test]
+    fn index_is_zero_during_blackout() {
+        let mut algorithm = VocIndexAlgorithm::new();
+
+        for second in 0..46 {
+            assert_eq!(algorithm.process(raw_signal(second)), 0);
+        }
+        assert_ne!(algorithm.process(raw_signal(46)), 0);
+    }
+
+
+    #[test]
+    fn index_matches_reference() {
+        // (second, VOC index) computed from raw_signal with the floating
+        // point C implementation this module follows, sampling every second.
+        let reference = [
+            (46, 1), (49, 4), (250, 103), (1000, 104), (1750, 106), (2500, 104),
+            (3250, 103), (3750, 102), (4000, 114), (4020, 354), (4040, 444),
+            (4100, 490), (4200, 476), (4280, 458), (4300, 438), (4320, 256),
+            (4340, 158), (4380, 109), (4500, 100), (5000, 101), (5750, 100),
+            (6000, 99), (6020, 46), (6060, 15), (6080, 12), (6100, 15),
+            (6120, 67), (6180, 80), (6250, 91), (6500, 99), (7000, 101),
+            (7750, 101),
+        ];
+        let mut algorithm = VocIndexAlgorithm::new();
+        let mut expected = reference.iter().peekable();
+
+        for second in 0..8_000 {
+            let index = algorithm.process(raw_signal(second));
+
+            if let Some((_, reference_index)) = expected.next_if(|(s, _)| *s == second) {
+                assert_eq!(index, *reference_index, "at {} s", second);
+            }
+        }
+        assert!(expected.peek().is_none());
+    }
+
+
+    #[test]
+    fn invalid_raw_signal_keeps_last_valid_one() {
+        let mut algorithm = VocIndexAlgorithm::new();
+        let mut reference = VocIndexAlgorithm::new();
+
+        for second in 0..600 {
+            assert_eq!(algorithm.process(raw_signal(second)), reference.process(raw_signal(second)));
+        }
+        let last = raw_signal(599);
+        for _ in 0..10 {
+            assert_eq!(algorithm.process(0), reference.process(last));
+            assert_eq!(algorithm.process(u16::MAX), reference.process(last));
+        }
+    }
+
+
+    #[test]
+    fn reset_starts_over() {
+        let mut algorithm = VocIndexAlgorithm::with_sampling_interval(5.0);
+
+        for second in 0..100 {
+            algorithm.process(raw_signal(second));
+        }
+        algorithm.reset();
+
+        assert_eq!(algorithm.sampling_interval_s(), 5.0);
+        assert_eq!(algorithm.process(raw_signal(0)), 0);
+    }
+}
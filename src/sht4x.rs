@@ -0,0 +1,305 @@
+// A driver for the Sensirion SHT40 and SHT41 temperature and humidity sensors.
+// Unlike the SCD30, they do not heat themselves up noticeably. This makes them
+// suitable as a reference for cross-checking the SCD30's temperature and
+// humidity readings.
+//
+// See 'Datasheet SHT4x' for the details.
+
+
+use core::fmt;
+use defmt::Format;
+use embedded_hal_1::delay::DelayNs;
+use embedded_hal_1::i2c::I2c;
+
+use crate::scd30;
+use crate::sensirion::{read_words, CrcError, ReadError};
+
+
+
+
+// A custom error type for reporting errors from both, the driver itself and
+// the underlying I2C implementation.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error<E> {
+    // The CRC of a word from a response did not match its content.
+    CrcError(CrcError),
+    I2cError(E),
+}
+
+
+// The repeatability of a measurement. Higher precision takes longer.
+#[derive(Clone, Copy, Debug, Eq, Format, PartialEq)]
+pub enum Precision {
+    High,
+    Medium,
+    Low,
+}
+
+
+#[derive(Clone, Copy, Debug, Format, PartialEq)]
+pub struct Measurement {
+    pub temperature_celsius: f32,
+    pub humidity_percent: f32,
+}
+
+
+// How much a measurement deviates from the reference. Positive values mean
+// the measurement reads higher than the reference.
+#[derive(Clone, Copy, Debug, Format, PartialEq)]
+pub struct Deviation {
+    pub temperature_kelvin: f32,
+    pub humidity_percent: f32,
+}
+
+
+pub struct Sht4x<I2C: I2c, D: DelayNs> {
+    i2c: I2C,
+    delay: D,
+}
+
+
+
+
+pub const I2C_ADDRESS: u8 = 0x44;
+
+
+// Commands from section 4.5 'Command Overview'.
+const CMD_MEASURE_HIGH_PRECISION: u8 = 0xfd;
+const CMD_MEASURE_MEDIUM_PRECISION: u8 = 0xf6;
+const CMD_MEASURE_LOW_PRECISION: u8 = 0xe0;
+const CMD_READ_SERIAL_NUMBER: u8 = 0x89;
+const CMD_SOFT_RESET: u8 = 0x94;
+
+// Maximum execution times from section 3.1 'Timings'.
+const MEASURE_HIGH_PRECISION_US: u32 = 8_300;
+const MEASURE_MEDIUM_PRECISION_US: u32 = 4_500;
+const MEASURE_LOW_PRECISION_US: u32 = 1_600;
+const COMMAND_EXECUTION_US: u32 = 1_000;
+
+
+
+
+// The I2C implementation's error gets formatted via its Debug implementation
+// as not all of them implement Format.
+impl<E: fmt::Debug> Format for Error<E> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::CrcError(error) => defmt::write!(f, "{}", error),
+            Error::I2cError(error) => defmt::write!(f, "I2C error: {}", defmt::Debug2Format(error)),
+        }
+    }
+}
+
+
+// Allow automatic conversion from the I2C implementation's error type to the
+// driver's error type (for the question mark operator).
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::I2cError(err)
+    }
+}
+
+
+// Allow automatic conversion from errors reading words via
+// sensirion::read_words (for the question mark operator).
+impl<E> From<ReadError<E>> for Error<E> {
+    fn from(error: ReadError<E>) -> Error<E> {
+        match error {
+            ReadError::CrcError(error) => Error::CrcError(error),
+            ReadError::I2cError(error) => Error::I2cError(error),
+        }
+    }
+}
+
+
+impl Precision {
+    fn command(&self) -> u8 {
+        match self {
+            Precision::High => CMD_MEASURE_HIGH_PRECISION,
+            Precision::Medium => CMD_MEASURE_MEDIUM_PRECISION,
+            Precision::Low => CMD_MEASURE_LOW_PRECISION,
+        }
+    }
+
+
+    fn duration_us(&self) -> u32 {
+        match self {
+            Precision::High => MEASURE_HIGH_PRECISION_US,
+            Precision::Medium => MEASURE_MEDIUM_PRECISION_US,
+            Precision::Low => MEASURE_LOW_PRECISION_US,
+        }
+    }
+}
+
+
+impl Measurement {
+    // Compares the temperature and humidity from the SCD30 against this
+    // measurement as reference. There is nothing to compare for measurements
+    // without them.
+    pub fn deviation(&self, measurement: &scd30::Measurement) -> Option<Deviation> {
+        Some(Deviation {
+            temperature_kelvin: measurement.temperature_celsius? - self.temperature_celsius,
+            humidity_percent: measurement.humidity_percent? - self.humidity_percent,
+        })
+    }
+}
+
+
+impl Deviation {
+    // Whether both values deviate at most by the given amounts.
+    pub fn is_within(&self, temperature_kelvin: f32, humidity_percent: f32) -> bool {
+        self.temperature_kelvin.abs() <= temperature_kelvin && self.humidity_percent.abs() <= humidity_percent
+    }
+}
+
+
+impl<I2C, D, E> Sht4x<I2C, D> where I2C: I2c<Error = E>, D: DelayNs {
+    // The driver takes ownership of a delay for waiting for measurements to
+    // complete.
+    pub fn new(i2c: I2C, delay: D) -> Self {
+        Sht4x{ i2c, delay }
+    }
+
+
+    // Gives back the I2C bus and the delay.
+    pub fn release(self) -> (I2C, D) {
+        (self.i2c, self.delay)
+    }
+
+
+    pub fn measure(&mut self, precision: Precision) -> Result<Measurement, Error<E>> {
+        let [temperature, humidity] = self.read_response(precision.command(), precision.duration_us())?;
+        Ok(measurement_from_words(temperature, humidity))
+    }
+
+
+    pub fn read_serial_number(&mut self) -> Result<u32, Error<E>> {
+        let [high, low] = self.read_response(CMD_READ_SERIAL_NUMBER, COMMAND_EXECUTION_US)?;
+        Ok(u32::from(high) << 16 | u32::from(low))
+    }
+
+
+    pub fn soft_reset(&mut self) -> Result<(), Error<E>> {
+        self.i2c.write(I2C_ADDRESS, &[CMD_SOFT_RESET])?;
+        self.delay.delay_us(COMMAND_EXECUTION_US);
+        Ok(())
+    }
+
+
+    // Issues a command, waits for its execution and reads its two words
+    // response after checking their CRCs.
+    fn read_response(&mut self, command: u8, execution_us: u32) -> Result<[u16; 2], Error<E>> {
+        self.i2c.write(I2C_ADDRESS, &[command])?;
+        self.delay.delay_us(execution_us);
+
+        Ok(read_words(&mut self.i2c, I2C_ADDRESS)?)
+    }
+}
+
+
+
+
+// Converts the raw words from the sensor into physical units as described in
+// section 4.6 'Conversion of Signal Output'. The humidity gets cropped to the
+// physically meaningful range.
+fn measurement_from_words(temperature: u16, humidity: u16) -> Measurement {
+    Measurement {
+        temperature_celsius: -45.0 + 175.0 * temperature as f32 / 65535.0,
+        humidity_percent: (-6.0 + 125.0 * humidity as f32 / 65535.0).clamp(0.0, 100.0),
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RecordingDelay;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+    use std::cell::Cell;
+
+
+    #[test]
+    fn measure_waits_for_conversion() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0xfd]),
+            Transaction::read(I2C_ADDRESS, vec![0x66, 0x66, 0x93, 0x80, 0x00, 0xa2]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let paused_ns = Cell::new(0);
+        let mut sensor = Sht4x::new(i2c.clone(), RecordingDelay(&paused_ns));
+
+        let measurement = sensor.measure(Precision::High).unwrap();
+        assert!((measurement.temperature_celsius - 25.0).abs() < 0.01);
+        assert!((measurement.humidity_percent - 56.5).abs() < 0.01);
+        assert!(paused_ns.get() >= 8_300_000);
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn measure_crops_humidity() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0xe0]),
+            Transaction::read(I2C_ADDRESS, vec![0x66, 0x66, 0x93, 0x00, 0x00, 0x81]),
+            Transaction::write(I2C_ADDRESS, vec![0xe0]),
+            Transaction::read(I2C_ADDRESS, vec![0x66, 0x66, 0x93, 0xff, 0xff, 0xac]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Sht4x::new(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.measure(Precision::Low).unwrap().humidity_percent, 0.0);
+        assert_eq!(sensor.measure(Precision::Low).unwrap().humidity_percent, 100.0);
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn measure_reports_crc_error() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0xf6]),
+            Transaction::read(I2C_ADDRESS, vec![0x66, 0x66, 0x93, 0x80, 0x00, 0xa3]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Sht4x::new(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.measure(Precision::Medium), Err(Error::CrcError(CrcError{ word: 1, received: 0xa3, expected: 0xa2 })));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn read_serial_number() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x89]),
+            Transaction::read(I2C_ADDRESS, vec![0x12, 0x34, 0x37, 0x56, 0x78, 0x7d]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Sht4x::new(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.read_serial_number(), Ok(0x1234_5678));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn deviation_from_reference() {
+        let reference = Measurement{ temperature_celsius: 21.5, humidity_percent: 45.0 };
+        let measurement = scd30::Measurement{ co2_ppm: 600.0, temperature_celsius: Some(23.0), humidity_percent: Some(40.0) };
+
+        let deviation = reference.deviation(&measurement).unwrap();
+
+        assert_eq!(deviation, Deviation{ temperature_kelvin: 1.5, humidity_percent: -5.0 });
+        assert!(deviation.is_within(1.5, 5.0));
+        assert!(!deviation.is_within(1.0, 5.0));
+
+        let co2_only = scd30::Measurement{ temperature_celsius: None, humidity_percent: None, ..measurement };
+        assert_eq!(reference.deviation(&co2_only), None);
+    }
+}
@@ -0,0 +1,540 @@
+// A driver for the Sensirion SPS30 particulate matter sensor. It uses the same
+// framing and CRC as the SCD30 for commands and responses. Measured values
+// get transferred as big-endian IEEE 754 floats spanning two words each.
+//
+// See 'Datasheet SPS30 Particulate Matter Sensor' for the details.
+
+
+use core::fmt;
+use core::marker::PhantomData;
+use defmt::Format;
+use embedded_hal_1::delay::DelayNs;
+use embedded_hal_1::i2c::I2c;
+use heapless::String;
+
+use crate::scd30::ModeChangeError;
+use crate::sensirion::{
+    command_frame,
+    command_frame_len,
+    command_with_argument_frame,
+    encode_command,
+    read_words,
+    CrcError,
+    ReadError,
+};
+
+
+
+
+// A custom error type for reporting errors from both, the driver itself and
+// the underlying I2C implementation.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error<E> {
+    // The CRC of a word from a response did not match its content.
+    CrcError(CrcError),
+    // A value decoded from the response is invalid, like a serial number
+    // which is not ASCII. Carries the index of the offending word within the
+    // response.
+    InvalidValue{ word: u8 },
+    I2cError(E),
+}
+
+
+// Marker types for the sensor's mode. Starting and stopping measurement
+// consumes the driver and returns it in the new mode. Commands which only
+// make sense in one mode are only available there.
+pub struct Idle;
+pub struct Measuring;
+
+
+pub struct Sps30<I2C: I2c, D: DelayNs, MODE = Idle> {
+    i2c: I2C,
+    delay: D,
+    mode: PhantomData<MODE>,
+}
+
+
+// Mass concentrations are cumulative: PM2.5 includes PM1.0 and so on. The
+// same applies to the number concentrations.
+#[derive(Clone, Copy, Debug, Format, PartialEq)]
+pub struct Measurement {
+    pub pm1_0_ug_m3: f32,
+    pub pm2_5_ug_m3: f32,
+    pub pm4_0_ug_m3: f32,
+    pub pm10_ug_m3: f32,
+    pub pm0_5_per_cm3: f32,
+    pub pm1_0_per_cm3: f32,
+    pub pm2_5_per_cm3: f32,
+    pub pm4_0_per_cm3: f32,
+    pub pm10_per_cm3: f32,
+    pub typical_particle_size_um: f32,
+}
+
+
+// The device status register from section 4.4 'Device Status Register'. The
+// flags stay set until the register gets cleared.
+#[derive(Clone, Copy, Debug, Eq, Format, PartialEq)]
+pub struct DeviceStatus(u32);
+
+
+// The serial number is an ASCII string of up to 32 characters.
+pub type SerialNumber = String<32>;
+
+
+
+
+pub const I2C_ADDRESS: u8 = 0x69;
+
+
+// Command words from section 6.3 'I2C Commands'.
+const CMD_START_MEASUREMENT: u16 = 0x0010;
+const CMD_STOP_MEASUREMENT: u16 = 0x0104;
+const CMD_READ_DATA_READY_FLAG: u16 = 0x0202;
+const CMD_READ_MEASURED_VALUES: u16 = 0x0300;
+const CMD_START_FAN_CLEANING: u16 = 0x5607;
+const CMD_AUTO_CLEANING_INTERVAL: u16 = 0x8004;
+const CMD_READ_SERIAL_NUMBER: u16 = 0xd033;
+const CMD_READ_DEVICE_STATUS_REGISTER: u16 = 0xd206;
+const CMD_CLEAR_DEVICE_STATUS_REGISTER: u16 = 0xd210;
+const CMD_DEVICE_RESET: u16 = 0xd304;
+
+// Requests measured values as floats. The sensor would report integers
+// otherwise.
+const OUTPUT_FORMAT_FLOAT: u16 = 0x0300;
+
+// Execution times of the commands. The sensor does not respond to other
+// commands until they have passed.
+const COMMAND_EXECUTION_MS: u32 = 20;
+const DEVICE_RESET_MS: u32 = 100;
+
+// Bits of the device status register.
+const STATUS_FAN_SPEED_WARNING: u32 = 1 << 21;
+const STATUS_LASER_FAILURE: u32 = 1 << 5;
+const STATUS_FAN_FAILURE: u32 = 1 << 4;
+
+// Ten floats with two words each.
+const MEASUREMENT_WORDS: usize = 20;
+
+
+
+
+// The I2C implementation's error gets formatted via its Debug implementation
+// as not all of them implement Format.
+impl<E: fmt::Debug> Format for Error<E> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::CrcError(error) => defmt::write!(f, "{}", error),
+            Error::InvalidValue{ word } => defmt::write!(f, "invalid value in word {=u8}", word),
+            Error::I2cError(error) => defmt::write!(f, "I2C error: {}", defmt::Debug2Format(error)),
+        }
+    }
+}
+
+
+// Allow automatic conversion from the I2C implementation's error type to the
+// driver's error type (for the question mark operator).
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::I2cError(err)
+    }
+}
+
+
+// Allow automatic conversion from errors reading words via
+// sensirion::read_words (for the question mark operator).
+impl<E> From<ReadError<E>> for Error<E> {
+    fn from(error: ReadError<E>) -> Error<E> {
+        match error {
+            ReadError::CrcError(error) => Error::CrcError(error),
+            ReadError::I2cError(error) => Error::I2cError(error),
+        }
+    }
+}
+
+
+impl DeviceStatus {
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
+
+
+    // Whether the fan runs more than 10 % off its target speed. This is
+    // expected for a couple of seconds after starting measurement.
+    pub fn fan_speed_warning(&self) -> bool {
+        self.0 & STATUS_FAN_SPEED_WARNING != 0
+    }
+
+
+    pub fn laser_failure(&self) -> bool {
+        self.0 & STATUS_LASER_FAILURE != 0
+    }
+
+
+    // Whether the fan is mechanically blocked or broken.
+    pub fn fan_failure(&self) -> bool {
+        self.0 & STATUS_FAN_FAILURE != 0
+    }
+}
+
+
+impl<I2C, D, E> Sps30<I2C, D, Idle> where I2C: I2c<Error = E>, D: DelayNs {
+    // The driver takes ownership of a delay for waiting for commands to
+    // complete.
+    pub fn new(i2c: I2C, delay: D) -> Self {
+        Sps30{ i2c, delay, mode: PhantomData }
+    }
+
+
+    // Starts measuring every second with measured values as floats.
+    pub fn start_measurement(self) -> Result<Sps30<I2C, D, Measuring>, ModeChangeError<Error<E>, Self>> {
+        let mut sensor = self;
+        let frame = command_with_argument_frame(CMD_START_MEASUREMENT, OUTPUT_FORMAT_FLOAT);
+        defmt::trace!("command: {=[u8]}", frame);
+
+        match sensor.write_frame(&frame, COMMAND_EXECUTION_MS) {
+            Ok(()) => Ok(sensor.into_mode()),
+            Err(error) => Err(ModeChangeError{ error, sensor }),
+        }
+    }
+}
+
+
+impl<I2C, D, E> Sps30<I2C, D, Measuring> where I2C: I2c<Error = E>, D: DelayNs {
+    pub fn stop_measurement(self) -> Result<Sps30<I2C, D, Idle>, ModeChangeError<Error<E>, Self>> {
+        let mut sensor = self;
+
+        match sensor.write_command(CMD_STOP_MEASUREMENT, COMMAND_EXECUTION_MS) {
+            Ok(()) => Ok(sensor.into_mode()),
+            Err(error) => Err(ModeChangeError{ error, sensor }),
+        }
+    }
+
+
+    pub fn is_measurement_ready(&mut self) -> Result<bool, Error<E>> {
+        self.write_command(CMD_READ_DATA_READY_FLAG, 0)?;
+        let [ready] = read_words(&mut self.i2c, I2C_ADDRESS)?;
+        Ok(ready == 1u16)
+    }
+
+
+    // Reads the latest measured values. Reading them before a new
+    // measurement is ready returns the previous ones again.
+    pub fn read_measurement(&mut self) -> Result<Measurement, Error<E>> {
+        self.write_command(CMD_READ_MEASURED_VALUES, 0)?;
+        let words = read_words(&mut self.i2c, I2C_ADDRESS)?;
+        Ok(measurement_from_words(&words))
+    }
+
+
+    // Runs the fan at maximum speed for 10 s to blow out dust. Measured
+    // values are not updated meanwhile.
+    pub fn start_fan_cleaning(&mut self) -> Result<(), Error<E>> {
+        self.write_command(CMD_START_FAN_CLEANING, COMMAND_EXECUTION_MS)
+    }
+}
+
+
+impl<I2C, D, E, MODE> Sps30<I2C, D, MODE> where I2C: I2c<Error = E>, D: DelayNs {
+    // Gives back the I2C bus and the delay.
+    pub fn release(self) -> (I2C, D) {
+        (self.i2c, self.delay)
+    }
+
+
+    // The interval in seconds in which the sensor cleans its fan while
+    // measuring. Defaults to 604800 (one week).
+    pub fn get_auto_cleaning_interval(&mut self) -> Result<u32, Error<E>> {
+        self.write_command(CMD_AUTO_CLEANING_INTERVAL, 0)?;
+        let [high, low] = read_words(&mut self.i2c, I2C_ADDRESS)?;
+        Ok(u32::from(high) << 16 | u32::from(low))
+    }
+
+
+    // Sets the auto cleaning interval in seconds. Zero disables it. The
+    // sensor keeps the interval across power cycles but only applies it
+    // after a reset or power cycle.
+    pub fn set_auto_cleaning_interval(&mut self, seconds: u32) -> Result<(), Error<E>> {
+        let mut frame = [0u8; command_frame_len(2)];
+        encode_command(CMD_AUTO_CLEANING_INTERVAL, &[(seconds >> 16) as u16, seconds as u16], &mut frame);
+        defmt::trace!("command: {=[u8]}", frame);
+
+        self.write_frame(&frame, COMMAND_EXECUTION_MS)
+    }
+
+
+    // Reads the serial number up to its terminating NUL. Fails with an
+    // invalid value if it contains anything but ASCII.
+    pub fn read_serial_number(&mut self) -> Result<SerialNumber, Error<E>> {
+        self.write_command(CMD_READ_SERIAL_NUMBER, 0)?;
+        let words: [u16; 16] = read_words(&mut self.i2c, I2C_ADDRESS)?;
+
+        let bytes = words.iter()
+            .flat_map(|word| word.to_be_bytes())
+            .take_while(|byte| *byte != 0);
+        let mut serial_number = SerialNumber::new();
+        for (index, byte) in bytes.enumerate() {
+            let invalid = Error::InvalidValue{ word: (index / 2) as u8 };
+            if !byte.is_ascii() {
+                return Err(invalid);
+            }
+            serial_number.push(char::from(byte)).map_err(|_| invalid)?;
+        }
+
+        Ok(serial_number)
+    }
+
+
+    pub fn read_device_status(&mut self) -> Result<DeviceStatus, Error<E>> {
+        self.write_command(CMD_READ_DEVICE_STATUS_REGISTER, 0)?;
+        let [high, low] = read_words(&mut self.i2c, I2C_ADDRESS)?;
+        Ok(DeviceStatus(u32::from(high) << 16 | u32::from(low)))
+    }
+
+
+    pub fn clear_device_status(&mut self) -> Result<(), Error<E>> {
+        self.write_command(CMD_CLEAR_DEVICE_STATUS_REGISTER, COMMAND_EXECUTION_MS)
+    }
+
+
+    // Resets the sensor which stops measurement.
+    pub fn reset(self) -> Result<Sps30<I2C, D, Idle>, ModeChangeError<Error<E>, Self>> {
+        let mut sensor = self;
+
+        match sensor.write_command(CMD_DEVICE_RESET, DEVICE_RESET_MS) {
+            Ok(()) => Ok(sensor.into_mode()),
+            Err(error) => Err(ModeChangeError{ error, sensor }),
+        }
+    }
+
+
+    fn into_mode<NEW>(self) -> Sps30<I2C, D, NEW> {
+        Sps30{ i2c: self.i2c, delay: self.delay, mode: PhantomData }
+    }
+
+
+    fn write_command(&mut self, command: u16, execution_ms: u32) -> Result<(), Error<E>> {
+        let frame = command_frame(command);
+        defmt::trace!("command: {=[u8]}", frame);
+
+        self.write_frame(&frame, execution_ms)
+    }
+
+
+    // Sends a complete command frame and waits for the sensor to execute
+    // it.
+    fn write_frame(&mut self, frame: &[u8], execution_ms: u32) -> Result<(), Error<E>> {
+        self.i2c.write(I2C_ADDRESS, frame)?;
+        self.delay.delay_ms(execution_ms);
+        Ok(())
+    }
+}
+
+
+
+
+// Converts the words from the sensor into the measured values. Each float
+// spans two words with the high word first.
+fn measurement_from_words(words: &[u16; MEASUREMENT_WORDS]) -> Measurement {
+    let value = |index: usize| f32::from_bits(u32::from(words[2 * index]) << 16 | u32::from(words[2 * index + 1]));
+
+    Measurement {
+        pm1_0_ug_m3: value(0),
+        pm2_5_ug_m3: value(1),
+        pm4_0_ug_m3: value(2),
+        pm10_ug_m3: value(3),
+        pm0_5_per_cm3: value(4),
+        pm1_0_per_cm3: value(5),
+        pm2_5_per_cm3: value(6),
+        pm4_0_per_cm3: value(7),
+        pm10_per_cm3: value(8),
+        typical_particle_size_um: value(9),
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensirion::{encode_words, frame_len};
+    use crate::testing::RecordingDelay;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+    use std::cell::Cell;
+
+
+    // Creates a driver for a sensor which is already measuring.
+    fn measuring(i2c: Mock) -> Sps30<Mock, NoopDelay, Measuring> {
+        Sps30{ i2c, delay: NoopDelay::new(), mode: PhantomData }
+    }
+
+
+    // Encodes the words into a response with CRCs.
+    fn response<const N: usize>(words: [u16; N]) -> Vec<u8> {
+        let mut frame = vec![0u8; frame_len(N)];
+        encode_words(&words, &mut frame);
+        frame
+    }
+
+
+    #[test]
+    fn start_measurement_requests_floats() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x00, 0x10, 0x03, 0x00, 0xac]),
+            Transaction::write(I2C_ADDRESS, vec![0x01, 0x04]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let paused_ns = Cell::new(0);
+        let sensor = Sps30::new(i2c.clone(), RecordingDelay(&paused_ns));
+
+        let sensor = sensor.start_measurement().unwrap();
+        let result = sensor.stop_measurement().map(|_| ()).map_err(|failure| failure.error);
+        assert_eq!(result, Ok(()));
+        assert!(paused_ns.get() >= 40_000_000);
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn is_measurement_ready() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x02, 0x02]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x00, 0x81]),
+            Transaction::write(I2C_ADDRESS, vec![0x02, 0x02]),
+            Transaction::read(I2C_ADDRESS, vec![0x00, 0x01, 0xb0]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = measuring(i2c.clone());
+
+        assert_eq!(sensor.is_measurement_ready(), Ok(false));
+        assert_eq!(sensor.is_measurement_ready(), Ok(true));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn read_measurement_decodes_floats() {
+        let values = [1.0f32, 2.5, 4.0, 10.0, 5.0, 6.0, 7.0, 8.0, 9.0, 0.5];
+        let mut words = [0u16; MEASUREMENT_WORDS];
+        for (pair, value) in words.chunks_exact_mut(2).zip(values.iter()) {
+            let bits = value.to_bits();
+            pair.copy_from_slice(&[(bits >> 16) as u16, bits as u16]);
+        }
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x03, 0x00]),
+            Transaction::read(I2C_ADDRESS, response(words)),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = measuring(i2c.clone());
+
+        let measurement = sensor.read_measurement().unwrap();
+        assert_eq!(measurement, Measurement {
+            pm1_0_ug_m3: 1.0,
+            pm2_5_ug_m3: 2.5,
+            pm4_0_ug_m3: 4.0,
+            pm10_ug_m3: 10.0,
+            pm0_5_per_cm3: 5.0,
+            pm1_0_per_cm3: 6.0,
+            pm2_5_per_cm3: 7.0,
+            pm4_0_per_cm3: 8.0,
+            pm10_per_cm3: 9.0,
+            typical_particle_size_um: 0.5,
+        });
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn read_measurement_reports_crc_error() {
+        let mut frame = response([0x3f80; MEASUREMENT_WORDS]);
+        frame[frame_len(3) + 2] ^= 0x01;
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x03, 0x00]),
+            Transaction::read(I2C_ADDRESS, frame),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = measuring(i2c.clone());
+
+        assert_eq!(sensor.read_measurement(), Err(Error::CrcError(CrcError{ word: 3, received: 0xd1, expected: 0xd0 })));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn auto_cleaning_interval() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0x80, 0x04]),
+            Transaction::read(I2C_ADDRESS, response([0x0009, 0x3a80])),
+            Transaction::write(I2C_ADDRESS, [vec![0x80, 0x04], response([0x0000, 0x0000])].concat()),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Sps30::new(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.get_auto_cleaning_interval(), Ok(604_800));
+        sensor.set_auto_cleaning_interval(0).unwrap();
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn read_serial_number_stops_at_nul() {
+        let mut words = [0u16; 16];
+        words[..4].copy_from_slice(&[0x4142, 0x3132, 0x3334, 0x3500]);
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0xd0, 0x33]),
+            Transaction::read(I2C_ADDRESS, response(words)),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Sps30::new(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.read_serial_number().unwrap().as_str(), "AB12345");
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn read_serial_number_rejects_non_ascii() {
+        let words = [0xc3a4; 16];
+        let mut ascii_first = [0x4142; 16];
+        ascii_first[5] = 0x43ff;
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0xd0, 0x33]),
+            Transaction::read(I2C_ADDRESS, response(words)),
+            Transaction::write(I2C_ADDRESS, vec![0xd0, 0x33]),
+            Transaction::read(I2C_ADDRESS, response(ascii_first)),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = Sps30::new(i2c.clone(), NoopDelay::new());
+
+        assert_eq!(sensor.read_serial_number(), Err(Error::InvalidValue{ word: 0 }));
+        assert_eq!(sensor.read_serial_number(), Err(Error::InvalidValue{ word: 5 }));
+
+        i2c.done();
+    }
+
+
+    #[test]
+    fn read_device_status() {
+        let expectations = [
+            Transaction::write(I2C_ADDRESS, vec![0xd2, 0x06]),
+            Transaction::read(I2C_ADDRESS, response([0x0020, 0x0010])),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut sensor = measuring(i2c.clone());
+
+        let status = sensor.read_device_status().unwrap();
+        assert_eq!(status.raw(), 0x0020_0010);
+        assert!(status.fan_speed_warning());
+        assert!(!status.laser_failure());
+        assert!(status.fan_failure());
+
+        i2c.done();
+    }
+}
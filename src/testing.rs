@@ -0,0 +1,20 @@
+// Helpers shared by the tests of the drivers.
+
+
+use embedded_hal_1::delay::DelayNs;
+use std::cell::Cell;
+
+
+
+
+// Sums up all pauses requested by the driver.
+pub struct RecordingDelay<'a>(pub &'a Cell<u32>);
+
+
+
+
+impl DelayNs for RecordingDelay<'_> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.0.set(self.0.get() + ns);
+    }
+}
@@ -0,0 +1,215 @@
+// Drawing measurements, their history and some statistics for the e-paper
+// display of the dioxide binary. This does not depend on a particular display
+// and can be tested against a mock draw target.
+
+
+use core::fmt::{self, Display, Write};
+use embedded_graphics::{
+    geometry::{Point, Size},
+    mono_font::MonoTextStyle,
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{Line, PrimitiveStyle, Rectangle},
+    text::{Alignment, Text},
+};
+use embedded_vintage_fonts::FONT_6X8;
+use heapless::{
+    String,
+    spsc::Queue,
+};
+use profont::{PROFONT_24_POINT, PROFONT_7_POINT};
+
+use crate::scd30::ValidMeasurement;
+
+
+
+
+const MAX_CO2_PPM: f32 = 2_500f32;
+const TICKS_MARGIN: i32 = 1;
+const TICKS_SIZE: i32 = 2;
+
+
+// Formats a value which might be absent from a measurement. Absent values
+// show up as dashes. Formatting options like the precision get applied to
+// present values.
+pub struct OptionalValue(pub Option<f32>);
+
+
+
+
+impl Display for OptionalValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(value) => value.fmt(f),
+            None => f.write_str("--"),
+        }
+    }
+}
+
+
+pub fn draw_co2_history<D: DrawTarget<Color = BinaryColor>, const N: usize>(
+    target: &mut D,
+    destination: &Rectangle,
+    measurements: &Queue<ValidMeasurement, N>) -> Result<(), D::Error>
+{
+    let bar_style = PrimitiveStyle::with_fill(BinaryColor::On);
+    let tick_style = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
+
+    let plot_rect = history_plot_rect(destination);
+    let origin = plot_rect.top_left;
+
+    let bar_width = plot_rect.size.width / measurements.capacity() as u32;
+    let norm_height = plot_rect.size.height;
+    let ppm_height_scaler = norm_height as f32 / MAX_CO2_PPM;
+
+    // Draw axis tick marks.
+    for ppm in (0..=2500).step_by(500) {
+        let y = norm_height as i32 - (ppm as f32 * ppm_height_scaler) as i32;
+
+        let margin = Point::new(1, 0);
+        let delta = Point::new(1, 0);
+        let left_start = Point::new(-1, y) - margin;
+        let right_start = Point::new(1, y) + plot_rect.size.x_axis() + margin;
+
+        Line::new(left_start, left_start - delta)
+            .translate(origin)
+            .into_styled(tick_style)
+            .draw(target)?;
+        Line::new(right_start, right_start + delta)
+            .translate(origin)
+            .into_styled(tick_style)
+            .draw(target)?;
+    }
+
+    // Draw actual data.
+    for (index, measurement) in measurements.iter().enumerate() {
+        // TODO: Clean up type conversions below.
+        let offset = Point::new(index as i32 * bar_width as i32, 0);
+        let height = core::cmp::min(norm_height, (measurement.measurement().co2_ppm * ppm_height_scaler) as u32);
+
+        let pos = Point::new(0, (norm_height - height + 1) as i32);
+        let size = Size::new(bar_width, height);
+        let rect = Rectangle::new(origin + offset + pos, size);
+
+        defmt::debug!("{}: offset: {}, height: {}, rect: {}", index,
+            defmt::Debug2Format(&offset), height,
+            defmt::Debug2Format(&rect));
+
+        rect.into_styled(bar_style).draw(target)?;
+    }
+
+    Ok(())
+}
+
+
+pub fn draw_measurement<D: DrawTarget<Color = BinaryColor>>(target: &mut D, measurement: &ValidMeasurement) -> Result<(), D::Error> {
+    let measurement = measurement.measurement();
+    let label_style = MonoTextStyle::new(&FONT_6X8, BinaryColor::On);
+    let value_style = MonoTextStyle::new(&PROFONT_24_POINT, BinaryColor::On);
+    let mut message: String<16> = String::new();
+
+    let label_origin = Point::new(0, 0);
+    let value_origin = Point::new(128, 0);
+
+    Text::new("CO2 [ppm]", label_origin + Point::new(0, 13), label_style)
+        .draw(target)?;
+    write!(&mut message, "{:.2}", measurement.co2_ppm)
+        .expect("failed to write to buffer");
+    Text::with_alignment(&message, value_origin + Point::new(0, 40), value_style, Alignment::Right)
+        .draw(target)?;
+
+    Text::new("Temperature [°C]", label_origin + Point::new(0, 63), label_style)
+        .draw(target)?;
+    message.clear();
+    write!(&mut message, "{:.2}", OptionalValue(measurement.temperature_celsius))
+        .expect("failed to write to buffer");
+    Text::with_alignment(&message, value_origin + Point::new(0, 90), value_style, Alignment::Right)
+        .draw(target)?;
+
+    Text::new("Humidity [%]", label_origin + Point::new(0, 113), label_style)
+        .draw(target)?;
+    message.clear();
+    write!(&mut message, "{:.2}", OptionalValue(measurement.humidity_percent))
+        .expect("failed to write to buffer");
+    Text::with_alignment(&message, value_origin + Point::new(0, 140), value_style, Alignment::Right)
+        .draw(target)?;
+
+    Ok(())
+}
+
+
+pub fn draw_stats<D: DrawTarget<Color = BinaryColor>>(
+    target: &mut D,
+    updates: usize,
+    queue_len: usize) -> Result<(), D::Error>
+{
+    let style = MonoTextStyle::new(&PROFONT_7_POINT, BinaryColor::On);
+    let mut message: String<32> = String::new();
+
+    write!(&mut message, "updates: {}, queue: {}", updates, queue_len)
+        .expect("failed to write to buffer");
+    Text::new(&message, Point::new(5, 294), style).draw(target)?;
+
+    Ok(())
+}
+
+
+pub fn history_plot_rect(destination: &Rectangle) -> Rectangle {
+    let offset = TICKS_MARGIN + TICKS_SIZE;
+    destination.offset(-offset)
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scd30::Measurement;
+    use embedded_graphics::mock_display::MockDisplay;
+
+
+    fn measurement(co2_ppm: f32) -> ValidMeasurement {
+        Measurement{ co2_ppm, temperature_celsius: Some(20.0), humidity_percent: Some(50.0) }.validate().unwrap()
+    }
+
+
+    #[test]
+    fn optional_value_shows_dashes_when_absent() {
+        let mut message: String<16> = String::new();
+
+        write!(&mut message, "{:.2}|{:.2}", OptionalValue(Some(21.5)), OptionalValue(None)).unwrap();
+
+        assert_eq!(message.as_str(), "21.50|--");
+    }
+
+
+    #[test]
+    fn history_plot_leaves_room_for_ticks() {
+        let destination = Rectangle::new(Point::new(0, 0), Size::new(40, 28));
+        assert_eq!(history_plot_rect(&destination), Rectangle::new(Point::new(3, 3), Size::new(34, 22)));
+    }
+
+
+    #[test]
+    fn co2_history_draws_one_bar_per_measurement() {
+        let destination = Rectangle::new(Point::new(0, 0), Size::new(40, 28));
+        // A queue with N slots holds N - 1 measurements.
+        let mut measurements: Queue<ValidMeasurement, 3> = Queue::new();
+        measurements.enqueue(measurement(MAX_CO2_PPM)).unwrap();
+
+        let mut display = MockDisplay::new();
+        display.set_allow_overdraw(true);
+        draw_co2_history(&mut display, &destination, &measurements).unwrap();
+
+        // A measurement at the maximum fills the whole height of its slot
+        // while the slot for the missing second one stays empty.
+        assert_eq!(display.get_pixel(Point::new(11, 5)), Some(BinaryColor::On));
+        assert_eq!(display.get_pixel(Point::new(11, 24)), Some(BinaryColor::On));
+        assert_eq!(display.get_pixel(Point::new(28, 15)), None);
+
+        // Tick marks on both sides of the plot.
+        assert_eq!(display.get_pixel(Point::new(0, 25)), Some(BinaryColor::On));
+        assert_eq!(display.get_pixel(Point::new(39, 25)), Some(BinaryColor::On));
+    }
+}